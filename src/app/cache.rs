@@ -0,0 +1,679 @@
+use std::error::Error;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use axum::body::{Body, Bytes};
+use axum::http::{StatusCode, HeaderMap, HeaderName, HeaderValue, header};
+use chrono::DateTime;
+use openssl::sha::sha256;
+use serde_derive::{Serialize, Deserialize};
+use crate::config::ProxyCache;
+use crate::debug::log;
+
+const SIZE: usize = 64;
+const MAX_ENTRY: usize = 8;
+const DISK_SIZE: u64 = 1024;
+const MB: usize = 1024 * 1024;
+const STATUS: [u16; 6] = [200, 203, 301, 308, 404, 410];
+
+fn now () -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(time) => time.as_secs(),
+        Err(_) => 0
+    }
+}
+
+fn directives (value: Option<&str>) -> HashMap<String, Option<String>> {
+    let mut directives = HashMap::new();
+    for directive in value.unwrap_or("").split(',') {
+        let mut parts = directive.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        if !name.is_empty() {
+            directives.insert(name, parts.next().map(|value| {
+                value.trim().trim_matches('"').to_string()
+            }));
+        }
+    }
+    directives
+}
+
+fn seconds (
+    directives: &HashMap<String, Option<String>>,
+    name: &str
+) -> Option<u64> {
+    directives.get(name)?.as_ref()?.parse().ok()
+}
+
+fn date (value: Option<&HeaderValue>) -> Option<u64> {
+    let value = value?.to_str().ok()?;
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(date.timestamp().max(0) as u64)
+}
+
+// requests that must skip the cache entirely, checked on the headers sent
+// upstream so the credentials added by the template are seen too
+pub fn bypass (request: &HeaderMap) -> bool {
+    let control = directives(request.get(header::CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok()));
+    request.contains_key(header::AUTHORIZATION) ||
+        control.contains_key("no-store")
+}
+
+// requests that accept only a response validated by the upstream
+pub fn revalidate (request: &HashMap<String, String>) -> bool {
+    let control = directives(request.get("cache-control").map(|v| v.as_str()));
+    control.contains_key("no-cache") ||
+        seconds(&control, "max-age") == Some(0) ||
+        request.get("pragma").is_some_and(|value| value.contains("no-cache"))
+}
+
+#[derive(Serialize, Deserialize)]
+struct Meta {
+    key: String,
+    vary: Vec<(String, String)>,
+    status: u16,
+    headers: Vec<(String, String)>,
+    stored: u64,
+    age: u64,
+    ttl: u64,
+    swr: u64,
+    sie: u64
+}
+
+impl Meta {
+    fn new (
+        key: &str,
+        request: &HashMap<String, String>,
+        status: StatusCode,
+        headers: &HeaderMap
+    ) -> Option<Meta> {
+        if !STATUS.contains(&status.as_u16()) ||
+            headers.contains_key(header::SET_COOKIE)
+        {
+            return None;
+        }
+        let control = directives(headers.get(header::CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok()));
+        if control.contains_key("no-store") || control.contains_key("private") {
+            return None;
+        }
+
+        let mut vary: Vec<String> = Vec::new();
+        for value in headers.get_all(header::VARY) {
+            for name in value.to_str().unwrap_or("*").split(',') {
+                let name = name.trim().to_lowercase();
+                if !name.is_empty() {
+                    vary.push(name);
+                }
+            }
+        }
+        vary.sort();
+        vary.dedup();
+        if vary.iter().any(|name| name == "*") {
+            return None;
+        }
+
+        let stored = now();
+        let ttl = if control.contains_key("no-cache") {
+            Some(0)
+        } else {
+            seconds(&control, "s-maxage")
+                .or(seconds(&control, "max-age"))
+                .or(headers.get(header::EXPIRES).map(|expires| {
+                    let origin = date(headers.get(header::DATE))
+                        .unwrap_or(stored);
+                    date(Some(expires)).unwrap_or(0).saturating_sub(origin)
+                }))
+        };
+        let validated = headers.contains_key(header::ETAG) ||
+            headers.contains_key(header::LAST_MODIFIED);
+        let ttl = match ttl {
+            Some(ttl) => ttl,
+            None if validated => 0,
+            None => {
+                return None;
+            }
+        };
+        let (swr, sie) = if control.contains_key("must-revalidate") ||
+            control.contains_key("proxy-revalidate")
+        {
+            (0, 0)
+        } else {
+            (
+                seconds(&control, "stale-while-revalidate").unwrap_or(0),
+                seconds(&control, "stale-if-error").unwrap_or(0)
+            )
+        };
+        if ttl == 0 && swr == 0 && sie == 0 && !validated {
+            return None;
+        }
+
+        Some(Meta {
+            key: key.to_string(),
+            vary: vary.into_iter().map(|name| {
+                let value = request.get(&name).cloned().unwrap_or_default();
+                (name, value)
+            }).collect(),
+            status: status.as_u16(),
+            headers: headers.iter().filter(|(name, _)| {
+                *name != header::AGE && name.as_str() != "x-cache"
+            }).filter_map(|(name, value)| Some((
+                name.to_string(),
+                value.to_str().ok()?.to_string()
+            ))).collect(),
+            stored,
+            age: headers.get(header::AGE)
+                .and_then(|age| age.to_str().ok()?.parse().ok())
+                .unwrap_or(0),
+            ttl,
+            swr,
+            sie
+        })
+    }
+
+    fn variant (&self) -> String {
+        let mut variant = self.key.clone();
+        for (name, value) in &self.vary {
+            variant = format!("{}\n{}: {}", variant, name, value);
+        }
+        variant
+    }
+}
+
+pub struct Entry {
+    meta: Meta,
+    body: Bytes
+}
+
+impl Entry {
+    fn age (&self) -> u64 {
+        now().saturating_sub(self.meta.stored) + self.meta.age
+    }
+
+    fn size (&self) -> usize {
+        self.body.len() + self.meta.headers.iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum::<usize>()
+    }
+
+    pub fn key (&self) -> &str {
+        &self.meta.key
+    }
+
+    pub fn fresh (&self) -> bool {
+        self.age() < self.meta.ttl
+    }
+
+    pub fn stale_while_revalidate (&self) -> bool {
+        self.age() < self.meta.ttl + self.meta.swr
+    }
+
+    pub fn stale_if_error (&self) -> bool {
+        self.age() < self.meta.ttl + self.meta.sie
+    }
+
+    pub fn validators (&self) -> Vec<(String, String)> {
+        let mut validators = Vec::new();
+        for (name, value) in &self.meta.headers {
+            if name == "etag" {
+                validators.push(("if-none-match".to_string(), value.clone()));
+            } else if name == "last-modified" {
+                validators.push((
+                    "if-modified-since".to_string(),
+                    value.clone()
+                ));
+            }
+        }
+        validators
+    }
+
+    pub fn response (
+        &self,
+        result: &str,
+        request: &HashMap<String, String>
+    ) -> (StatusCode, HeaderMap, Body) {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.meta.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value)
+            ) {
+                headers.append(name, value);
+            }
+        }
+        headers.insert(header::AGE, HeaderValue::from(self.age()));
+        if let Ok(result) = HeaderValue::from_str(result) {
+            headers.insert(HeaderName::from_static("x-cache"), result);
+        }
+
+        let etag = headers.get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok());
+        let matched = match (request.get("if-none-match"), etag) {
+            (Some(tags), Some(etag)) => tags.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") ==
+                    etag.trim_start_matches("W/")
+            }),
+            _ => false
+        };
+        if matched {
+            headers.remove(header::CONTENT_LENGTH);
+            return (StatusCode::NOT_MODIFIED, headers, Body::empty());
+        }
+
+        let status = match StatusCode::from_u16(self.meta.status) {
+            Ok(status) => status,
+            Err(_) => StatusCode::OK
+        };
+        (status, headers, Body::from(self.body.clone()))
+    }
+}
+
+// values ordered by the tick of their last use, so the least recently used
+// is found without scanning them all
+struct Lru<V> {
+    values: HashMap<String, (V, u64)>,
+    order: BTreeSet<(u64, String)>
+}
+
+impl<V> Default for Lru<V> {
+    fn default () -> Lru<V> {
+        Lru {
+            values: HashMap::new(),
+            order: BTreeSet::new()
+        }
+    }
+}
+
+impl<V> Lru<V> {
+    fn contains_key (&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    fn get (&mut self, key: &str, tick: u64) -> Option<&V> {
+        let (value, used) = self.values.get_mut(key)?;
+        self.order.remove(&(*used, key.to_string()));
+        self.order.insert((tick, key.to_string()));
+        *used = tick;
+        Some(value)
+    }
+
+    fn insert (&mut self, key: String, value: V, tick: u64) -> Option<V> {
+        let old = self.remove(&key);
+        self.order.insert((tick, key.clone()));
+        self.values.insert(key, (value, tick));
+        old
+    }
+
+    fn remove (&mut self, key: &str) -> Option<V> {
+        let (value, used) = self.values.remove(key)?;
+        self.order.remove(&(used, key.to_string()));
+        Some(value)
+    }
+
+    fn pop (&mut self) -> Option<(String, V)> {
+        let (_, key) = self.order.pop_first()?;
+        let (value, _) = self.values.remove(&key)?;
+        Some((key, value))
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    entries: Lru<Arc<Entry>>,
+    varies: HashMap<String, Vec<String>>,
+    files: Lru<u64>,
+    size: usize,
+    disk: u64,
+    tick: u64,
+    updating: HashSet<String>
+}
+
+#[derive(Clone)]
+pub struct Cache {
+    store: Arc<Mutex<Store>>,
+    size: usize,
+    max_entry: usize,
+    dir: Option<PathBuf>,
+    disk_size: u64
+}
+
+fn file (variant: &str) -> String {
+    sha256(variant.as_bytes()).iter()
+        .map(|byte| format!("{:02x}", byte)).collect()
+}
+
+async fn read (path: &Path) -> Result<Entry, Box<dyn Error + Send + Sync>> {
+    let data = tokio::fs::read(path).await?;
+    let split = match data.iter().position(|byte| *byte == b'\n') {
+        Some(split) => split,
+        None => {
+            return Err("Missing cache entry metadata".into());
+        }
+    };
+    let meta: Meta = serde_json::from_slice(&data[..split])?;
+    Ok(Entry {
+        meta,
+        body: Bytes::copy_from_slice(&data[split + 1..])
+    })
+}
+
+// the temporary file is unique, the same entry may be written concurrently
+async fn write (
+    path: &Path,
+    tmp: &Path,
+    entry: &Entry
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let mut data = serde_json::to_vec(&entry.meta)?;
+    data.push(b'\n');
+    data.extend_from_slice(&entry.body);
+    tokio::fs::write(tmp, &data).await?;
+    tokio::fs::rename(tmp, path).await?;
+    Ok(data.len() as u64)
+}
+
+// the name of an entry, the sha256 of its variant in hex
+fn entry (name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+// an entry and the tick of a write that did not finish
+fn temporary (name: &str) -> bool {
+    match name.split_once('.') {
+        Some((name, tick)) => entry(name) && !tick.is_empty() &&
+            tick.bytes().all(|byte| byte.is_ascii_digit()),
+        None => false
+    }
+}
+
+// the entries left on disk by a previous run, other files are kept
+fn scan (dir: &Path, store: &Mutex<Store>) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    for file in fs::read_dir(dir)?.flatten() {
+        let path = file.path();
+        let name = file.file_name().to_string_lossy().to_string();
+        if temporary(&name) {
+            let _ = fs::remove_file(&path);
+            continue;
+        }
+        if !entry(&name) {
+            continue;
+        }
+        let mut line = String::new();
+        let meta = match fs::File::open(&path) {
+            Ok(f) => match BufReader::new(f).read_line(&mut line) {
+                Ok(_) => serde_json::from_str::<Meta>(&line).ok(),
+                Err(_) => None
+            },
+            Err(_) => None
+        };
+        if let (Some(meta), Ok(data)) = (meta, file.metadata()) {
+            let mut store = match store.lock() {
+                Ok(store) => store,
+                Err(err) => err.into_inner()
+            };
+            // the entries stored meanwhile are newer
+            if store.files.contains_key(&name) {
+                continue;
+            }
+            store.files.insert(name, data.len(), 0);
+            store.disk += data.len();
+            store.varies.entry(meta.key).or_insert(
+                meta.vary.into_iter().map(|(name, _)| name).collect()
+            );
+        }
+    }
+    Ok(())
+}
+
+impl Cache {
+    pub fn new (config: &ProxyCache) -> Cache {
+        let store = Arc::new(Mutex::new(Store::default()));
+        // the disk is read in background, the entries are found once loaded
+        if let Some(dir) = config.dir.clone() {
+            let store = store.clone();
+            thread::spawn(move || {
+                if let Err(err) = scan(&dir, &store) {
+                    log(&format!(
+                        "Unable to load cache dir <{}>\n{:#}",
+                        dir.display(), err
+                    ));
+                }
+            });
+        }
+
+        Cache {
+            store,
+            size: config.size.unwrap_or(SIZE) * MB,
+            max_entry: config.max_entry.unwrap_or(MAX_ENTRY) * MB,
+            dir: config.dir.clone(),
+            disk_size: config.disk_size.unwrap_or(DISK_SIZE) * MB as u64
+        }
+    }
+
+    fn lock (&self) -> MutexGuard<'_, Store> {
+        match self.store.lock() {
+            Ok(store) => store,
+            Err(err) => err.into_inner()
+        }
+    }
+
+    fn insert (&self, store: &mut Store, variant: String, entry: Arc<Entry>) {
+        if let Some(old) = store.entries.remove(&variant) {
+            store.size -= old.size();
+        }
+        while store.size + entry.size() > self.size {
+            match store.entries.pop() {
+                Some((_, old)) => store.size -= old.size(),
+                None => break
+            }
+        }
+        store.tick += 1;
+        store.size += entry.size();
+        let tick = store.tick;
+        store.entries.insert(variant, entry, tick);
+    }
+
+    pub async fn get (
+        &self,
+        key: &str,
+        request: &HashMap<String, String>
+    ) -> Option<Arc<Entry>> {
+        let mut variant = key.to_string();
+        {
+            let mut store = self.lock();
+            for name in store.varies.get(key)? {
+                variant = format!("{}\n{}: {}", variant, name,
+                    request.get(name).map(|v| v.as_str()).unwrap_or(""));
+            }
+            store.tick += 1;
+            let tick = store.tick;
+            if let Some(entry) = store.entries.get(&variant, tick) {
+                return Some(entry.clone());
+            }
+        }
+
+        let dir = self.dir.as_ref()?;
+        let name = file(&variant);
+        {
+            let mut store = self.lock();
+            let tick = store.tick;
+            store.files.get(&name, tick)?;
+        }
+        match read(&dir.join(&name)).await {
+            Ok(entry) if entry.meta.variant() == variant => {
+                let entry = Arc::new(entry);
+                self.insert(&mut self.lock(), variant, entry.clone());
+                Some(entry)
+            },
+            _ => None
+        }
+    }
+
+    // the entry is written to disk in background
+    fn store (&self, meta: Meta, body: Bytes) -> Arc<Entry> {
+        let variant = meta.variant();
+        let entry = Arc::new(Entry {meta, body});
+
+        let mut store = self.lock();
+        store.varies.insert(
+            entry.meta.key.clone(),
+            entry.meta.vary.iter().map(|(name, _)| name.clone()).collect()
+        );
+        if entry.size() <= self.max_entry.min(self.size) {
+            self.insert(&mut store, variant.clone(), entry.clone());
+        }
+        drop(store);
+
+        if let Some(dir) = self.dir.clone() {
+            tokio::spawn(self.clone().persist(dir, variant, entry.clone()));
+        }
+        entry
+    }
+
+    async fn persist (self, dir: PathBuf, variant: String, entry: Arc<Entry>) {
+        let name = file(&variant);
+        let tmp = {
+            let mut store = self.lock();
+            store.tick += 1;
+            dir.join(format!("{}.{}", name, store.tick))
+        };
+        let size = match write(&dir.join(&name), &tmp, &entry).await {
+            Ok(size) => size,
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&tmp).await;
+                log(&format!(
+                    "Fail to write cache entry <{}>\n{:#}",
+                    entry.meta.key, err
+                ));
+                return;
+            }
+        };
+
+        let mut evicted: Vec<String> = Vec::new();
+        {
+            let mut store = self.lock();
+            store.tick += 1;
+            let tick = store.tick;
+            if let Some(old) = store.files.insert(name, size, tick) {
+                store.disk -= old;
+            }
+            store.disk += size;
+            while store.disk > self.disk_size {
+                match store.files.pop() {
+                    Some((oldest, size)) => {
+                        store.disk -= size;
+                        evicted.push(oldest);
+                    },
+                    None => break
+                }
+            }
+        }
+        for name in evicted {
+            let _ = tokio::fs::remove_file(dir.join(name)).await;
+        }
+    }
+
+    pub fn revalidate (
+        &self,
+        entry: &Entry,
+        request: &HashMap<String, String>,
+        headers: &HeaderMap
+    ) -> Arc<Entry> {
+        let mut merged = HeaderMap::new();
+        for (name, value) in &entry.meta.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value)
+            ) {
+                merged.append(name, value);
+            }
+        }
+        for name in headers.keys() {
+            if name != header::CONTENT_LENGTH {
+                merged.remove(name);
+                for value in headers.get_all(name) {
+                    merged.append(name, value.clone());
+                }
+            }
+        }
+
+        let status = match StatusCode::from_u16(entry.meta.status) {
+            Ok(status) => status,
+            Err(_) => StatusCode::OK
+        };
+        match Meta::new(&entry.meta.key, request, status, &merged) {
+            Some(meta) => self.store(meta, entry.body.clone()),
+            None => Arc::new(Entry {
+                meta: Meta {
+                    stored: now(),
+                    age: 0,
+                    ttl: 0,
+                    swr: 0,
+                    sie: 0,
+                    headers: entry.meta.headers.clone(),
+                    vary: entry.meta.vary.clone(),
+                    key: entry.meta.key.clone(),
+                    status: entry.meta.status
+                },
+                body: entry.body.clone()
+            })
+        }
+    }
+
+    pub fn pending (
+        &self,
+        key: &str,
+        request: &HashMap<String, String>,
+        status: StatusCode,
+        headers: &HeaderMap
+    ) -> Option<Pending> {
+        let length: Option<usize> = headers.get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse().ok());
+        if length.unwrap_or(0) > self.max_entry {
+            return None;
+        }
+        Some(Pending {
+            cache: self.clone(),
+            meta: Meta::new(key, request, status, headers)?,
+            length,
+            body: Vec::new()
+        })
+    }
+
+    // only one background revalidation of each entry at a time
+    pub fn update (&self, entry: &Entry) -> bool {
+        self.lock().updating.insert(entry.meta.variant())
+    }
+
+    pub fn updated (&self, entry: &Entry) {
+        self.lock().updating.remove(&entry.meta.variant());
+    }
+}
+
+pub struct Pending {
+    cache: Cache,
+    meta: Meta,
+    length: Option<usize>,
+    body: Vec<u8>
+}
+
+impl Pending {
+    pub fn push (&mut self, chunk: &[u8]) -> bool {
+        self.body.extend_from_slice(chunk);
+        self.body.len() <= self.cache.max_entry
+    }
+
+    // the body may not be polled again after its declared length
+    pub fn complete (&self) -> bool {
+        self.length == Some(self.body.len())
+    }
+
+    pub fn finish (self) -> Arc<Entry> {
+        self.cache.store(self.meta, self.body.into())
+    }
+}
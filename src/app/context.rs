@@ -1,8 +1,11 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use serde::Serializer;
 use serde_derive::Serialize;
-use axum::http::{Uri, HeaderMap, Method};
+use axum::http::{Uri, HeaderMap, Method, header::HOST};
 use axum::body::Bytes;
 use axum::extract::MatchedPath;
+use crate::tls::Client;
 
 #[derive(Serialize)]
 pub struct Context {
@@ -14,7 +17,23 @@ pub struct Context {
     params: HashMap<String, String>,
     vars: HashMap<String, String>,
     pub headers: HashMap<String, String>,
-    pub body: Vec<u8>
+    pub client: Option<Client>,
+    #[serde(skip)]
+    pub addr: Option<SocketAddr>,
+    #[serde(skip)]
+    pub https: bool,
+    #[serde(skip)]
+    pub host: Option<String>,
+    #[serde(serialize_with = "bytes")]
+    pub body: Bytes
+}
+
+// the body is shared with the proxy, but the templates see a list of bytes
+fn bytes<S: Serializer> (
+    body: &Bytes,
+    serializer: S
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(body.iter())
 }
 
 impl Context {
@@ -25,7 +44,7 @@ impl Context {
         method: Method,
         url: Uri,
         raw_headers: HeaderMap,
-        body: Bytes 
+        body: Bytes
     ) -> Context {
         let mut headers: HashMap<String, String> = HashMap::new();
         for (key, value) in raw_headers.iter() {
@@ -33,6 +52,11 @@ impl Context {
                 headers.insert(key.to_string(), value.to_string());
             }
         }
+        // HTTP/2 requests carry the host in the authority of the uri
+        let host = match raw_headers.get(HOST) {
+            Some(host) => host.to_str().ok().map(|host| host.to_string()),
+            None => url.authority().map(|host| host.to_string())
+        };
         Context {
             method: method.as_str().to_string(),
             url: url.to_string(),
@@ -42,7 +66,11 @@ impl Context {
             params,
             vars,
             headers,
-            body: body.to_vec()
+            client: None,
+            addr: None,
+            https: false,
+            host,
+            body
         }
     }
 }
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use axum::http::HeaderMap;
+
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade"
+];
+
+const FORWARDING: [&str; 4] = [
+    "forwarded",
+    "x-forwarded-for",
+    "x-forwarded-host",
+    "x-forwarded-proto"
+];
+
+#[derive(Clone)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u32
+}
+
+impl Network {
+    pub fn new (network: &str) -> Result<Network, String> {
+        let error = || format!("Invalid trusted proxy <{}>", network);
+        let (addr, prefix) = match network.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (network, None)
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| error())?;
+        let bits = if addr.is_ipv4() {32} else {128};
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().map_err(|_| error())?,
+            None => bits
+        };
+        if prefix > bits {
+            return Err(error());
+        }
+
+        Ok(Network {addr, prefix})
+    }
+
+    fn contains (&self, ip: IpAddr) -> bool {
+        let mask = |bits: u32| {
+            if self.prefix == 0 {0} else {u128::MAX << (bits - self.prefix)}
+        };
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = mask(32) as u32;
+                u32::from(network) & mask == u32::from(ip) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = mask(128);
+                u128::from(network) & mask == u128::from(ip) & mask
+            },
+            _ => false
+        }
+    }
+}
+
+fn connection (value: Option<&str>) -> Vec<String> {
+    value.unwrap_or("").split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn node (ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip)
+    }
+}
+
+// the host comes from the client, escaped so it can not add parameters
+fn quoted (value: &str) -> String {
+    let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", value)
+}
+
+pub fn request (
+    headers: &HashMap<String, String>,
+    addr: Option<SocketAddr>,
+    https: bool,
+    host: Option<&String>,
+    trusted: &[Network],
+    websocket: bool
+) -> Vec<(String, String)> {
+    let mut remove = connection(headers.get("connection").map(|v| v.as_str()));
+    remove.extend(HOP_BY_HOP.iter().map(|name| name.to_string()));
+    remove.extend(FORWARDING.iter().map(|name| name.to_string()));
+    remove.push(String::from("host"));
+
+    let mut result: Vec<(String, String)> = headers.iter()
+        .filter(|(name, _)| !remove.contains(name))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    if websocket {
+        if let Some(upgrade) = headers.get("upgrade") {
+            result.push((String::from("connection"), String::from("upgrade")));
+            result.push((String::from("upgrade"), upgrade.clone()));
+        }
+    }
+
+    let ip = addr.map(|addr| addr.ip().to_canonical());
+    let trusted = ip.is_some_and(|ip| {
+        trusted.iter().any(|network| network.contains(ip))
+    });
+    let incoming = |name: &str| -> Option<&String> {
+        if trusted {headers.get(name)} else {None}
+    };
+    let proto = String::from(if https {"https"} else {"http"});
+
+    let mut forwarded: Vec<String> = Vec::new();
+    if let Some(ip) = ip {
+        forwarded.push(format!("for={}", node(ip)));
+        result.push((String::from("x-forwarded-for"),
+            match incoming("x-forwarded-for") {
+                Some(value) => format!("{}, {}", value, ip),
+                None => ip.to_string()
+            }
+        ));
+    }
+    forwarded.push(format!("proto={}", proto));
+    result.push((String::from("x-forwarded-proto"),
+        incoming("x-forwarded-proto").cloned().unwrap_or(proto)
+    ));
+    if let Some(host) = host {
+        forwarded.push(format!("host={}", quoted(host)));
+        result.push((String::from("x-forwarded-host"),
+            incoming("x-forwarded-host").unwrap_or(host).clone()
+        ));
+    }
+    let forwarded = forwarded.join(";");
+    result.push((String::from("forwarded"), match incoming("forwarded") {
+        Some(value) => format!("{}, {}", value, forwarded),
+        None => forwarded
+    }));
+
+    result
+}
+
+pub fn response (headers: &mut HeaderMap, upgraded: bool) {
+    let value = headers.get("connection")
+        .and_then(|value| value.to_str().ok());
+    let mut remove = connection(value);
+    remove.extend(HOP_BY_HOP.iter().map(|name| name.to_string()));
+    for name in remove {
+        if upgraded && (name == "connection" || name == "upgrade") {
+            continue;
+        }
+        headers.remove(name);
+    }
+}
@@ -1,34 +1,64 @@
 mod context;
 mod proxy;
 mod modify;
+mod tunnel;
+mod forward;
+mod cache;
+mod rewrite;
 
 use std::error::Error;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use minijinja::{Environment};
 use axum::{
-    extract::{Path, Query, State, OriginalUri, MatchedPath},
-    body::{Bytes, Body},
+    Extension,
+    extract::{Path, Query, State, OriginalUri, MatchedPath, ConnectInfo},
+    body::{Bytes, Body, HttpBody, to_bytes},
+    response::{IntoResponse, Response},
     http::{Method, StatusCode, HeaderMap, HeaderName, HeaderValue, header},
 };
+use hyper::upgrade::OnUpgrade;
+use http_body_util::LengthLimitError;
 use context::Context;
-use proxy::Proxy;
+use proxy::{Proxy, Gateway};
+pub use proxy::ProxyOptions;
+pub use cache::Cache;
 use modify::Modify;
 use crate::debug::debug;
+use crate::errors::{Failure, failure};
+use crate::tls::Client;
 use mime_guess;
 
+const BODY_LIMIT: usize = 2 * 1024 * 1024;
+
 type Env = Environment<'static>;
 #[derive(Clone)]
 pub struct AppState {
     env: Env,
     template: String,
-    mime: Option<HeaderValue>
+    mime: Option<HeaderValue>,
+    client_cert: bool,
+    stream: bool,
+    body_limit: usize,
+    proxy: ProxyOptions
 }
 
 impl AppState {
-    pub fn new (env: &Env, template: &str) -> AppState {
+    pub fn new (
+        env: &Env,
+        template: &str,
+        client_cert: bool,
+        stream: bool,
+        body_limit: Option<usize>,
+        proxy: &ProxyOptions
+    ) -> AppState {
         AppState {
             env: env.clone(),
             template: template.to_string(),
+            client_cert,
+            stream,
+            body_limit: body_limit.unwrap_or(BODY_LIMIT),
+            proxy: proxy.clone(),
             mime: match mime_guess::from_path(template).first_raw() {
                 Some(mime) => match HeaderValue::from_str(mime) {
                     Ok(mime) => Some(mime),
@@ -40,8 +70,10 @@ impl AppState {
     }
 
     pub async fn run (&self,
-        ctx: &Context
-    ) -> Result<(StatusCode, HeaderMap, Body), Box<dyn Error>> {
+        ctx: &Context,
+        stream: Option<Body>,
+        upgrade: Option<OnUpgrade>
+    ) -> Result<(StatusCode, HeaderMap, Body), Box<dyn Error + Send + Sync>> {
         let tpl = self.env.get_template(&self.template)?;
         let (tpl, state) = match tpl.render_and_return_state(ctx) {
             Ok(result) => result,
@@ -61,13 +93,19 @@ impl AppState {
         let mut body: Body = tpl.into();
 
         if let Some(proxy) = state.lookup("proxy") {
+            let request = stream.filter(|stream| !stream.is_end_stream())
+                .map(|stream| {
+                    reqwest::Body::wrap_stream(stream.into_data_stream())
+                });
+            let websocket = ctx.headers.get("upgrade")
+                .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
             (status, headers, body) = Proxy::new(
-                &ctx.method,
-                &ctx.headers,
-                &ctx.body,
-                &proxy
+                ctx,
+                request,
+                &proxy,
+                &self.proxy,
+                if websocket {upgrade} else {None}
             ).await?;
-            headers.remove(header::TRANSFER_ENCODING);
         } else if let Some(mime) = &self.mime {
             headers.insert(header::CONTENT_TYPE, mime.clone());
         }
@@ -111,20 +149,65 @@ pub async fn handler (
     route: MatchedPath,
     headers: HeaderMap,
     method: Method,
-    body: Bytes,
-) -> (StatusCode, HeaderMap, Body) {
-    let ctx = Context::new(route, params, vars, method, url, headers, body);
+    client: Option<Extension<Option<Client>>>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    upgrade: Option<Extension<OnUpgrade>>,
+    body: Body,
+) -> Response {
+    // only https connections have the client certificate extension
+    let https = client.is_some();
+    let client = client.and_then(|Extension(client)| client);
+    if state.client_cert && client.is_none() {
+        let status = StatusCode::FORBIDDEN;
+        let error = "Client certificate required";
+        debug(method.as_str(), &url.to_string(), Some(status.as_u16()), error);
+        return failure(status, error);
+    }
+    let (body, stream) = if !state.stream {
+        match to_bytes(body, state.body_limit).await {
+            Ok(body) => (body, None),
+            Err(err) => {
+                let error = err.to_string();
+                let status = if err.into_inner().is::<LengthLimitError>() {
+                    StatusCode::PAYLOAD_TOO_LARGE
+                } else {
+                    StatusCode::BAD_REQUEST
+                };
+                debug(
+                    method.as_str(),
+                    &url.to_string(),
+                    Some(status.as_u16()),
+                    &error
+                );
+                return failure(status, &error);
+            }
+        }
+    } else {
+        (Bytes::new(), Some(body))
+    };
+    let mut ctx = Context::new(route, params, vars, method, url, headers, body);
+    ctx.client = client;
+    ctx.addr = addr.map(|ConnectInfo(addr)| addr);
+    ctx.https = https;
     debug(&ctx.method, &ctx.url, None, "");
-    match state.run(&ctx).await {
+    let upgrade = upgrade.map(|Extension(upgrade)| upgrade);
+    match state.run(&ctx, stream, upgrade).await {
         Ok(response) => {
             debug(&ctx.method, &ctx.url, Some(response.0.as_u16()), "");
-            response
+            response.into_response()
         },
         Err(err) => {
             let error = err.to_string();
-            let status = StatusCode::INTERNAL_SERVER_ERROR;
+            let status = match err.downcast_ref::<Gateway>() {
+                Some(gateway) => gateway.status,
+                None => StatusCode::INTERNAL_SERVER_ERROR
+            };
             debug(&ctx.method, &ctx.url, Some(status.as_u16()), &error);
-            (status, HeaderMap::new(), error.into())
+            let mut response = (
+                status, HeaderMap::new(), Body::from(error.clone())
+            ).into_response();
+            response.extensions_mut().insert(Failure(error));
+            response
         }
     }
 }
@@ -1,65 +1,502 @@
 use std::error::Error;
 use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use serde::Deserialize;
 use serde_derive::Deserialize;
 use minijinja::Value;
-use axum::http::{StatusCode, HeaderMap};
-use axum::body::Body;
-use reqwest::{Request, RequestBuilder, Client};
+use axum::http::{StatusCode, HeaderMap, HeaderName, HeaderValue, header};
+use axum::body::{Body, Bytes, to_bytes};
+use hyper::upgrade::OnUpgrade;
+use reqwest::{Request, RequestBuilder, Response, Client};
+use futures_util::StreamExt;
+use futures_util::stream::unfold;
 use crate::debug::debug;
+use crate::upstream::{Upstreams, Pool, Peer};
+use crate::client::{Clients, Timeouts};
+use crate::unix;
+use super::context::Context;
+use super::tunnel::tunnel;
+use super::cache::{self, Cache, Entry, Pending};
+use super::rewrite::{Rewrite, Redirect};
+use super::forward::{self, Network};
+
+const IDLE_TIMEOUT: u64 = 60;
+const BACKOFF: u64 = 100;
+const IDEMPOTENT: [&str; 6] = [
+    "GET", "HEAD", "OPTIONS", "PUT", "DELETE", "TRACE"
+];
+// responses of a backend that failed, retried like the transport errors
+const RETRY_STATUS: [u16; 3] = [502, 503, 504];
+
+#[derive(Debug)]
+pub struct Gateway {
+    pub status: StatusCode,
+    message: String
+}
+
+impl fmt::Display for Gateway {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for Gateway {}
+
+type SendError = Box<dyn Error + Send + Sync>;
+
+impl Gateway {
+    fn error (status: StatusCode, message: String) -> SendError {
+        Box::new(Gateway {status, message})
+    }
+}
+
+#[derive(Clone)]
+pub struct ProxyOptions {
+    pub upstreams: Upstreams,
+    pub clients: Clients,
+    trusted: Vec<Network>,
+    cache: Option<Cache>
+}
+
+impl ProxyOptions {
+    pub fn new (
+        upstreams: Upstreams,
+        clients: &Clients,
+        trusted: &[String],
+        cache: Option<Cache>
+    ) -> Result<ProxyOptions, String> {
+        let mut networks: Vec<Network> = Vec::new();
+        for network in trusted {
+            networks.push(Network::new(network)?);
+        }
+
+        Ok(ProxyOptions {
+            upstreams,
+            clients: clients.clone(),
+            trusted: networks,
+            cache
+        })
+    }
+}
+
+// everything needed to send the request again, in retries and in the
+// background revalidation of the cache
+#[derive(Clone)]
+struct Target {
+    method: String,
+    path: String,
+    socket: Option<PathBuf>,
+    pool: Option<Arc<Pool>>,
+    client: Client,
+    timeouts: Timeouts,
+    headers: HeaderMap,
+    replay: Option<Bytes>,
+    timeout: Option<Duration>,
+    retries: u32,
+    request: HashMap<String, String>,
+    addr: Option<SocketAddr>
+}
+
+impl Target {
+    async fn send (
+        &self,
+        body: Option<reqwest::Body>,
+        extra: &[(String, String)]
+    ) -> Result<(Response, String, Option<Peer>), SendError> {
+        let mut body = body;
+        let retries = if self.replay.is_some() {self.retries} else {0};
+        let mut attempt = 0;
+        let mut last: Option<Peer> = None;
+        loop {
+            let peer = match &self.pool {
+                Some(pool) => match pool.select(&self.request, self.addr) {
+                    Ok(peer) => Some(peer),
+                    // the failures may have excluded every backend, the
+                    // retry goes back to the last one
+                    Err(_) if last.is_some() => last.take(),
+                    Err(err) => {
+                        return Err(Gateway::error(
+                            StatusCode::SERVICE_UNAVAILABLE, err
+                        ));
+                    }
+                },
+                None => None
+            };
+            let url = match &peer {
+                Some(peer) => peer.url(&self.path),
+                None => self.path.clone()
+            };
+
+            debug(&self.method, &url, None, "");
+            let mut r = RequestBuilder::from_parts(self.client.clone(),
+                Request::new(self.method.parse()?, url.parse()?)
+            );
+            r = r.headers(self.headers.clone());
+            for (name, value) in extra {
+                r = r.header(name, value);
+            }
+            if let Some(timeout) = self.timeout {
+                r = r.timeout(timeout);
+            }
+            let body = match (body.take(), &self.replay) {
+                (Some(body), _) => body,
+                (None, Some(replay)) => replay.clone().into(),
+                (None, None) => reqwest::Body::from(Vec::new())
+            };
+            let socket = match &peer {
+                Some(peer) => peer.socket(),
+                None => self.socket.as_deref()
+            };
+            let result: Result<Response, SendError> = match socket {
+                Some(socket) => match r.body(body).build() {
+                    Ok(request) => {
+                        unix::send(socket, request, self.timeouts).await
+                    },
+                    Err(err) => Err(err.into())
+                },
+                None => match tokio::time::timeout(
+                    self.timeouts.header, r.body(body).send()
+                ).await {
+                    Ok(response) => response.map_err(|err| err.into()),
+                    Err(_) => Err(io::Error::new(
+                        io::ErrorKind::TimedOut, "Response headers timed out"
+                    ).into())
+                }
+            };
+            match result {
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    debug(&self.method, &url, Some(status), "");
+                    let failed = RETRY_STATUS.contains(&status);
+                    if let Some(peer) = &peer {
+                        if failed {
+                            peer.failure();
+                        } else {
+                            peer.success();
+                        }
+                    }
+                    if !failed || attempt >= retries {
+                        return Ok((response, url, peer));
+                    }
+                },
+                Err(err) => {
+                    let status = if timed_out(err.as_ref()) {
+                        StatusCode::GATEWAY_TIMEOUT
+                    } else {
+                        StatusCode::BAD_GATEWAY
+                    };
+                    let error = err.to_string();
+                    debug(&self.method, &url, Some(status.as_u16()), &error);
+                    if let Some(peer) = &peer {
+                        peer.failure();
+                    }
+                    if attempt >= retries {
+                        return Err(Gateway::error(status, error));
+                    }
+                }
+            }
+            last = peer;
+            let backoff = BACKOFF << attempt.min(6);
+            tokio::time::sleep(Duration::from_millis(backoff)).await;
+            attempt += 1;
+        }
+    }
+}
+
+fn timed_out (err: &(dyn Error + 'static)) -> bool {
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return err.is_timeout();
+    }
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|err| err.kind() == io::ErrorKind::TimedOut)
+}
+
+// streams the response body, keeping the peer busy until it ends and
+// storing it in the cache when complete
+fn stream (
+    response: Response,
+    peer: Option<Peer>,
+    pending: Option<Pending>
+) -> Body {
+    let pending = match pending {
+        Some(pending) if pending.complete() => {
+            pending.finish();
+            None
+        },
+        pending => pending
+    };
+    Body::from_stream(unfold(
+        (response.bytes_stream(), peer, pending),
+        |(mut inner, peer, mut pending)| async move {
+            match inner.next().await {
+                Some(Ok(chunk)) => {
+                    if let Some(store) = &mut pending {
+                        if !store.push(&chunk) {
+                            pending = None;
+                        }
+                    }
+                    if pending.as_ref().is_some_and(|store| store.complete()) {
+                        if let Some(store) = pending.take() {
+                            store.finish();
+                        }
+                    }
+                    Some((Ok(chunk), (inner, peer, pending)))
+                },
+                Some(Err(err)) => Some((Err(err), (inner, peer, None))),
+                None => {
+                    if let Some(pending) = pending {
+                        pending.finish();
+                    }
+                    None
+                }
+            }
+        }
+    ))
+}
+
+async fn refresh (cache: Cache, target: Target, entry: Arc<Entry>) {
+    if let Ok((response, _, peer)) = target.send(
+        None, &entry.validators()
+    ).await {
+        let status = response.status();
+        let mut headers = response.headers().clone();
+        forward::response(&mut headers, false);
+        if status == StatusCode::NOT_MODIFIED {
+            cache.revalidate(&entry, &target.request, &headers);
+        } else if let Some(pending) = cache.pending(
+            entry.key(), &target.request, status, &headers
+        ) {
+            let body = stream(response, peer, Some(pending));
+            let _ = to_bytes(body, usize::MAX).await;
+        }
+    }
+    cache.updated(&entry);
+}
+
+async fn cached (
+    cache: &Cache,
+    key: &str,
+    target: Target
+) -> Result<(StatusCode, HeaderMap, Body), SendError> {
+    let request = &target.request;
+    let entry = cache.get(key, request).await;
+    let revalidate = cache::revalidate(request);
+    if let Some(entry) = &entry {
+        if !revalidate && entry.fresh() {
+            return Ok(entry.response("HIT", request));
+        }
+        if !revalidate && entry.stale_while_revalidate() {
+            if cache.update(entry) {
+                tokio::spawn(refresh(
+                    cache.clone(), target.clone(), entry.clone()
+                ));
+            }
+            return Ok(entry.response("STALE", request));
+        }
+    }
+
+    let validators = match &entry {
+        Some(entry) => entry.validators(),
+        None => Vec::new()
+    };
+    let (response, _, peer) = match target.send(None, &validators).await {
+        Ok(result) => result,
+        Err(err) => match entry {
+            Some(entry) if entry.stale_if_error() => {
+                return Ok(entry.response("STALE", request));
+            },
+            _ => {
+                return Err(err);
+            }
+        }
+    };
+
+    let status = response.status();
+    let mut headers = response.headers().clone();
+    forward::response(&mut headers, false);
+    if let Some(entry) = entry {
+        if status == StatusCode::NOT_MODIFIED {
+            let entry = cache.revalidate(&entry, request, &headers);
+            return Ok(entry.response("REVALIDATED", request));
+        }
+        if status.is_server_error() && entry.stale_if_error() {
+            return Ok(entry.response("STALE", request));
+        }
+    }
+    let pending = cache.pending(key, request, status, &headers);
+    headers.insert(
+        HeaderName::from_static("x-cache"),
+        HeaderValue::from_static("MISS")
+    );
+
+    Ok((status, headers, stream(response, peer, pending)))
+}
 
 #[derive(Deserialize)]
 pub struct Proxy {
     method: Option<String>,
+    upstream: Option<String>,
     url: String,
     headers: Option<HashMap<String, String>>,
-    body: Option<Vec<u8>>
+    body: Option<Vec<u8>>,
+    idle_timeout: Option<u64>,
+    timeout: Option<u64>,
+    retries: Option<u32>,
+    cache: Option<bool>,
+    socket: Option<PathBuf>,
+    redirect: Option<Redirect>,
+    cookie_domain: Option<HashMap<String, String>>,
+    cookie_path: Option<HashMap<String, String>>
 }
 
 impl Proxy {
     pub async fn new (
-        method: &str,
-        headers: &HashMap<String, String>,
-        body: &Vec<u8>,
-        proxy: &Value
-    ) -> Result<(StatusCode, HeaderMap, Body), Box<dyn Error>> {
+        ctx: &Context,
+        streamed: Option<reqwest::Body>,
+        proxy: &Value,
+        options: &ProxyOptions,
+        upgrade: Option<OnUpgrade>
+    ) -> Result<(StatusCode, HeaderMap, Body), SendError> {
         let proxy = Proxy::deserialize(proxy)?;
-        let method = proxy.method.unwrap_or(method.to_string());
-
-        debug(&method, &proxy.url, None, "");
-        let mut r = RequestBuilder::from_parts(Client::new(),
-            Request::new(method.parse()?, proxy.url.parse()?)
-        );
-        if let Some(headers) = proxy.headers {
-            for (name, value) in headers.iter() {
-                r = r.header(name, value);
+        let method = proxy.method.unwrap_or(ctx.method.clone());
+        let pool = match &proxy.upstream {
+            Some(name) => match options.upstreams.get(name) {
+                Some(pool) => Some(pool),
+                None => {
+                    return Err(format!("Unknown upstream <{}>", name).into());
+                }
+            },
+            None => None
+        };
+        let mut headers = HeaderMap::new();
+        for (name, value) in forward::request(
+            &ctx.headers,
+            ctx.addr,
+            ctx.https,
+            ctx.host.as_ref(),
+            &options.trusted,
+            upgrade.is_some()
+        ) {
+            if name == "content-length" && proxy.body.is_some() {
+                continue;
+            }
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(&value)
+            ) {
+                headers.append(name, value);
             }
         }
-        for (name, value) in headers.iter() {
-            r = r.header(name.clone(), value.clone());
+        // the headers of the template replace the forwarded ones
+        for (name, value) in proxy.headers.unwrap_or_default() {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(&value)
+            ) {
+                headers.insert(name, value);
+            }
         }
-        let response = match r.body(
-            proxy.body.unwrap_or(body.to_vec())
-        ).send().await {
-            Ok(response) => {
-                debug(
-                    &method,
-                    &proxy.url,
-                    Some(response.status().as_u16()),
-                    ""
-                );
-                response
+        // a streamed body can only be sent once
+        let (body, replay) = match (proxy.body, streamed) {
+            (Some(body), _) => (None, Some(Bytes::from(body))),
+            (None, Some(stream)) => (Some(stream), None),
+            (None, None) => (None, Some(ctx.body.clone()))
+        };
+        let idempotent = IDEMPOTENT.contains(&method.as_str());
+        let mut target = Target {
+            timeout: proxy.timeout.map(Duration::from_secs)
+                .or(pool.as_ref().and_then(|pool| pool.timeout)),
+            retries: match &replay {
+                Some(_) if idempotent => proxy.retries
+                    .or(pool.as_ref().map(|pool| pool.retries))
+                    .unwrap_or(0),
+                _ => 0
             },
-            Err(err) => {
-                debug(&method, &proxy.url, Some(500), &err.to_string());
-                return Err(err.into());
-            }
+            method,
+            // over a socket the url may be just the path
+            path: match &proxy.socket {
+                Some(_) if proxy.url.starts_with('/') => {
+                    format!("http://localhost{}", proxy.url)
+                },
+                _ => proxy.url
+            },
+            socket: proxy.socket,
+            client: match &pool {
+                Some(pool) if pool.http2 => options.clients.h2c.clone(),
+                _ => options.clients.http.clone()
+            },
+            timeouts: options.clients.timeouts,
+            pool,
+            headers,
+            replay,
+            request: ctx.headers.clone(),
+            addr: ctx.addr
+        };
+
+        let upstreams = match &target.pool {
+            Some(pool) => pool.urls(),
+            None => vec![target.path.clone()]
         };
+        let rewrite = Rewrite::new(
+            proxy.redirect,
+            proxy.cookie_domain,
+            proxy.cookie_path,
+            &upstreams,
+            ctx.host.as_ref().map(|host| format!("{}://{}",
+                if ctx.https {"https"} else {"http"}, host
+            ))
+        );
+
+        if let Some(cache) = &options.cache {
+            if proxy.cache.unwrap_or(true) && target.method == "GET" &&
+                upgrade.is_none() && target.replay.is_some() &&
+                !cache::bypass(&target.headers)
+            {
+                // the cache answers the conditional requests of the client
+                target.headers.remove(header::IF_NONE_MATCH);
+                target.headers.remove(header::IF_MODIFIED_SINCE);
+                let key = match (&proxy.upstream, &target.socket) {
+                    (Some(name), _) => format!("{}:{}", name, target.path),
+                    (None, Some(socket)) => {
+                        format!("{}:{}", socket.display(), target.path)
+                    },
+                    (None, None) => target.path.clone()
+                };
+                let (status, mut headers, body) =
+                    cached(cache, &key, target).await?;
+                rewrite.apply(&mut headers);
+                return Ok((status, headers, body));
+            }
+        }
+
+        let (response, url, peer) = target.send(body, &[]).await?;
+
+        let status = response.status();
+        let mut headers = response.headers().clone();
+        forward::response(
+            &mut headers,
+            upgrade.is_some() && status == StatusCode::SWITCHING_PROTOCOLS
+        );
+        rewrite.apply(&mut headers);
+        if let Some(upgrade) = upgrade {
+            if status == StatusCode::SWITCHING_PROTOCOLS {
+                let idle = proxy.idle_timeout.unwrap_or(IDLE_TIMEOUT);
+                tokio::spawn(tunnel(
+                    upgrade,
+                    response,
+                    Duration::from_secs(idle),
+                    url,
+                    peer
+                ));
+                return Ok((status, headers, Body::empty()));
+            }
+        }
 
-        Ok((
-            response.status(),
-            response.headers().clone(),
-            response.bytes().await?.into()
-        ))
+        Ok((status, headers, stream(response, peer, None)))
     }
 }
@@ -0,0 +1,159 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use serde_derive::Deserialize;
+use axum::http::{HeaderMap, HeaderValue, header};
+use reqwest::Url;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Redirect {
+    Auto(bool),
+    Replace(HashMap<String, String>)
+}
+
+// the longest prefixes are tried first
+fn sorted (map: HashMap<String, String>) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = map.into_iter().collect();
+    pairs.sort_by_key(|(from, _)| Reverse(from.len()));
+    pairs
+}
+
+fn replace (value: &str, pairs: &[(String, String)]) -> Option<String> {
+    for (from, to) in pairs {
+        if let Some(rest) = value.strip_prefix(from.as_str()) {
+            if from.ends_with('/') || rest.is_empty() ||
+                rest.starts_with(['/', '?', '#'])
+            {
+                let rest = match to.ends_with('/') {
+                    true => rest.strip_prefix('/').unwrap_or(rest),
+                    false => rest
+                };
+                return Some(format!("{}{}", to, rest));
+            }
+        }
+    }
+    None
+}
+
+pub struct Rewrite {
+    redirect: Vec<(String, String)>,
+    domain: Vec<(String, String)>,
+    path: Vec<(String, String)>
+}
+
+impl Rewrite {
+    pub fn new (
+        redirect: Option<Redirect>,
+        domain: Option<HashMap<String, String>>,
+        path: Option<HashMap<String, String>>,
+        upstreams: &[String],
+        public: Option<String>
+    ) -> Rewrite {
+        let redirect = match (redirect, public) {
+            (Some(Redirect::Replace(map)), _) => sorted(map),
+            (Some(Redirect::Auto(true)), Some(public)) => {
+                let mut map = HashMap::new();
+                for url in upstreams {
+                    if let Ok(url) = Url::parse(url) {
+                        map.insert(
+                            url.origin().ascii_serialization(),
+                            public.clone()
+                        );
+                    }
+                }
+                sorted(map)
+            },
+            _ => Vec::new()
+        };
+
+        Rewrite {
+            redirect,
+            domain: domain.map(sorted).unwrap_or_default(),
+            path: path.map(sorted).unwrap_or_default()
+        }
+    }
+
+    fn cookie (&self, cookie: &str) -> String {
+        let mut parts: Vec<String> = Vec::new();
+        for (index, part) in cookie.split(';').enumerate() {
+            let part = part.trim();
+            let (name, value) = match part.split_once('=') {
+                Some((name, value)) if index > 0 => {
+                    (name.trim(), value.trim())
+                },
+                _ => {
+                    parts.push(part.to_string());
+                    continue;
+                }
+            };
+            if name.eq_ignore_ascii_case("domain") {
+                let domain = value.trim_start_matches('.');
+                let to = self.domain.iter().find(|(from, _)| {
+                    from.trim_start_matches('.').eq_ignore_ascii_case(domain)
+                }).map(|(_, to)| to.as_str());
+                match to {
+                    Some("") => {},
+                    Some(to) => parts.push(format!("{}={}", name, to)),
+                    None => parts.push(part.to_string())
+                }
+            } else if name.eq_ignore_ascii_case("path") {
+                match replace(value, &self.path) {
+                    Some(path) => parts.push(format!("{}={}", name, path)),
+                    None => parts.push(part.to_string())
+                }
+            } else {
+                parts.push(part.to_string());
+            }
+        }
+        parts.join("; ")
+    }
+
+    pub fn apply (&self, headers: &mut HeaderMap) {
+        if !self.redirect.is_empty() {
+            for name in [header::LOCATION, header::CONTENT_LOCATION] {
+                let url = headers.get(&name)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| replace(value, &self.redirect))
+                    .and_then(|url| HeaderValue::from_str(&url).ok());
+                if let Some(url) = url {
+                    headers.insert(name, url);
+                }
+            }
+
+            // 5; url=http://upstream/path
+            let refresh = headers.get(header::REFRESH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| {
+                    let start = value.to_ascii_lowercase().find("url=")? + 4;
+                    let url = value[start..].trim_start_matches(['\'', '"']);
+                    let quote = value.len() - start - url.len();
+                    Some(format!("{}{}",
+                        &value[..start + quote],
+                        replace(url, &self.redirect)?
+                    ))
+                })
+                .and_then(|value| HeaderValue::from_str(&value).ok());
+            if let Some(refresh) = refresh {
+                headers.insert(header::REFRESH, refresh);
+            }
+        }
+
+        if !self.domain.is_empty() || !self.path.is_empty() {
+            let cookies: Vec<HeaderValue> = headers
+                .get_all(header::SET_COOKIE).iter()
+                .map(|cookie| cookie.to_str().ok()
+                    .and_then(|value| {
+                        HeaderValue::from_str(&self.cookie(value)).ok()
+                    })
+                    .unwrap_or(cookie.clone())
+                )
+                .collect();
+            if !cookies.is_empty() {
+                headers.remove(header::SET_COOKIE);
+                for cookie in cookies {
+                    headers.append(header::SET_COOKIE, cookie);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt, split};
+use tokio::time::sleep;
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
+use reqwest::Response;
+use crate::debug::log;
+use crate::upstream::Peer;
+
+const BUFFER: usize = 8192;
+
+async fn copy<A, B> (a: A, b: B, idle: Duration) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite,
+    B: AsyncRead + AsyncWrite
+{
+    let (mut a_read, mut a_write) = split(a);
+    let (mut b_read, mut b_write) = split(b);
+    let mut a_buffer = vec![0u8; BUFFER];
+    let mut b_buffer = vec![0u8; BUFFER];
+    let (mut sent, mut received) = (0, 0);
+
+    loop {
+        tokio::select! {
+            n = a_read.read(&mut a_buffer) => {
+                let n = n?;
+                if n == 0 {
+                    break;
+                }
+                b_write.write_all(&a_buffer[..n]).await?;
+                sent += n as u64;
+            },
+            n = b_read.read(&mut b_buffer) => {
+                let n = n?;
+                if n == 0 {
+                    break;
+                }
+                a_write.write_all(&b_buffer[..n]).await?;
+                received += n as u64;
+            },
+            _ = sleep(idle) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "idle timeout"
+                ));
+            }
+        }
+    }
+    let _ = a_write.shutdown().await;
+    let _ = b_write.shutdown().await;
+
+    Ok((sent, received))
+}
+
+pub async fn tunnel (
+    client: OnUpgrade,
+    upstream: Response,
+    idle: Duration,
+    url: String,
+    peer: Option<Peer>
+) {
+    let (client, upstream) = match (client.await, upstream.upgrade().await) {
+        (Ok(client), Ok(upstream)) => (client, upstream),
+        (Err(err), _) => {
+            log(&format!("Fail to upgrade <{}>!\n{}", url, err));
+            return;
+        },
+        (_, Err(err)) => {
+            log(&format!("Fail to upgrade <{}>!\n{}", url, err));
+            return;
+        }
+    };
+
+    log(&format!("WebSocket <{}> opened", url));
+    match copy(TokioIo::new(client), upstream, idle).await {
+        Ok((sent, received)) => log(&format!(
+            "WebSocket <{}> closed, {} bytes sent and {} bytes received",
+            url, sent, received
+        )),
+        Err(err) => log(&format!("WebSocket <{}> closed!\n{}", url, err))
+    }
+    drop(peer);
+}
@@ -0,0 +1,76 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, Utc};
+use axum::http::HeaderMap;
+use axum::http::header::{IF_NONE_MATCH, IF_MODIFIED_SINCE, IF_RANGE};
+
+fn seconds (time: SystemTime) -> u64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0
+    }
+}
+
+fn parse_date (value: &str) -> Option<u64> {
+    match DateTime::parse_from_rfc2822(value.trim()) {
+        Ok(date) => u64::try_from(date.timestamp()).ok(),
+        Err(_) => None
+    }
+}
+
+fn weak_eq (a: &str, b: &str) -> bool {
+    a.trim().trim_start_matches("W/") == b.trim().trim_start_matches("W/")
+}
+
+pub fn etag (len: u64, modified: Option<SystemTime>) -> String {
+    format!("\"{:x}-{:x}\"", modified.map_or(0, seconds), len)
+}
+
+pub fn http_date (time: SystemTime) -> String {
+    let time: DateTime<Utc> = time.into();
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+pub fn not_modified (
+    headers: &HeaderMap,
+    etag: &str,
+    modified: Option<SystemTime>
+) -> bool {
+    if let Some(value) = headers.get(IF_NONE_MATCH) {
+        let value = value.to_str().unwrap_or("");
+        return value.split(',').any(|tag| {
+            tag.trim() == "*" || weak_eq(tag, etag)
+        });
+    }
+
+    if let (Some(value), Some(modified)) = (
+        headers.get(IF_MODIFIED_SINCE), modified
+    ) {
+        if let Some(since) = parse_date(value.to_str().unwrap_or("")) {
+            return seconds(modified) <= since;
+        }
+    }
+
+    false
+}
+
+pub fn if_range (
+    headers: &HeaderMap,
+    etag: &str,
+    modified: Option<SystemTime>
+) -> bool {
+    let value = match headers.get(IF_RANGE) {
+        Some(value) => value.to_str().unwrap_or("").trim(),
+        None => {
+            return true;
+        }
+    };
+
+    if value.starts_with('"') || value.starts_with("W/") {
+        value == etag
+    } else {
+        match (parse_date(value), modified) {
+            (Some(date), Some(modified)) => seconds(modified) == date,
+            _ => false
+        }
+    }
+}
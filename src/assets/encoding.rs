@@ -0,0 +1,53 @@
+use std::path::{Path, PathBuf};
+use axum::http::{HeaderMap, header::ACCEPT_ENCODING};
+use tokio::fs::metadata;
+
+const PRECOMPRESSED: [(&str, &str); 3] = [
+    ("br", "br"),
+    ("zstd", "zst"),
+    ("gzip", "gz")
+];
+
+fn quality (value: &str) -> f32 {
+    let mut params = value.split(';').skip(1);
+    match params.find_map(|param| param.trim().strip_prefix("q=")) {
+        Some(q) => q.trim().parse().unwrap_or(0.0),
+        None => 1.0
+    }
+}
+
+pub async fn siblings (file: &Path) -> Vec<(&'static str, PathBuf)> {
+    let mut siblings = Vec::new();
+    for (encoding, ext) in PRECOMPRESSED {
+        let mut sibling = file.as_os_str().to_owned();
+        sibling.push(format!(".{}", ext));
+        let sibling = PathBuf::from(sibling);
+        if metadata(&sibling).await.is_ok_and(|meta| meta.is_file()) {
+            siblings.push((encoding, sibling));
+        }
+    }
+    siblings
+}
+
+pub fn accepted (headers: &HeaderMap) -> Vec<&'static str> {
+    let value = match headers.get(ACCEPT_ENCODING) {
+        Some(value) => value.to_str().unwrap_or(""),
+        None => {
+            return Vec::new();
+        }
+    };
+
+    let mut encodings: Vec<(f32, &'static str)> = Vec::new();
+    for (encoding, _) in PRECOMPRESSED {
+        let q = value.split(',').find(|item| {
+            item.split(';').next().unwrap_or("").trim()
+                .eq_ignore_ascii_case(encoding)
+        }).map_or(0.0, quality);
+        if q > 0.0 {
+            encodings.push((q, encoding));
+        }
+    }
+    encodings.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    encodings.into_iter().map(|(_, encoding)| encoding).collect()
+}
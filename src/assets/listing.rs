@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::SystemTime;
+use serde_derive::Serialize;
+use axum::{
+    response::Response,
+    http::{StatusCode, HeaderMap},
+    http::header::{ACCEPT, CONTENT_TYPE, VARY, HeaderValue},
+};
+use tokio::fs::{read_dir, metadata};
+use crate::debug::time_string;
+
+#[derive(Serialize)]
+pub struct Entry {
+    name: String,
+    is_dir: bool,
+    len: u64,
+    modified: String,
+    #[serde(skip)]
+    time: Option<SystemTime>
+}
+
+pub async fn entries (
+    dir: &Path,
+    visible: impl Fn(&str) -> bool
+) -> Result<Vec<Entry>, StatusCode> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut dir = match read_dir(dir).await {
+        Ok(dir) => dir,
+        Err(_) => {
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    while let Ok(Some(entry)) = dir.next_entry().await {
+        let name = match entry.file_name().to_str() {
+            Some(name) => name.to_string(),
+            None => continue
+        };
+        if !visible(&name) {
+            continue;
+        }
+        if let Ok(meta) = metadata(entry.path()).await {
+            let time = meta.modified().ok();
+            entries.push(Entry {
+                name,
+                is_dir: meta.is_dir(),
+                len: if meta.is_dir() {0} else {meta.len()},
+                modified: time.map_or(String::new(), |time| {
+                    time_string(time.into())
+                }),
+                time
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+fn sort (entries: &mut [Entry], by: &str, desc: bool) {
+    entries.sort_by(|a, b| {
+        let order = match by {
+            "size" => a.len.cmp(&b.len),
+            "modified" => a.time.cmp(&b.time),
+            _ => a.name.to_lowercase().cmp(&b.name.to_lowercase())
+        };
+        b.is_dir.cmp(&a.is_dir).then(if desc {order.reverse()} else {order})
+    });
+}
+
+fn escape (text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn encode (segment: &str) -> String {
+    let mut encoded = String::new();
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn html (path: &str, entries: &[Entry], by: &str, desc: bool) -> String {
+    let base: String = path.split('/').filter(|s| !s.is_empty())
+        .map(|s| format!("/{}", encode(s))).collect();
+    let column = |name: &str, title: &str| -> String {
+        let order = if by == name && !desc {"desc"} else {"asc"};
+        format!("<th><a href=\"?sort={}&amp;order={}\">{}</a></th>",
+            name, order, title
+        )
+    };
+
+    let mut rows = String::new();
+    if !base.is_empty() {
+        let parent = match base.rfind('/') {
+            Some(i) => &base[..i],
+            None => ""
+        };
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}/\">../</a></td><td></td><td></td></tr>\n",
+            parent
+        ));
+    }
+    for entry in entries {
+        let slash = if entry.is_dir {"/"} else {""};
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}/{}{}\">{}{}</a></td>\
+            <td>{}</td><td>{}</td></tr>\n",
+            base, encode(&entry.name), slash, escape(&entry.name), slash,
+            if entry.is_dir {String::new()} else {entry.len.to_string()},
+            entry.modified
+        ));
+    }
+
+    let title = escape(&format!("Index of /{}", path.trim_matches('/')));
+    format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+        <title>{}</title>\n</head>\n<body>\n<h1>{}</h1>\n<table>\n\
+        <tr>{}{}{}</tr>\n{}</table>\n</body>\n</html>\n",
+        title, title,
+        column("name", "Name"),
+        column("size", "Size"),
+        column("modified", "Modified"),
+        rows
+    )
+}
+
+pub fn response (
+    path: &str,
+    mut entries: Vec<Entry>,
+    headers: &HeaderMap,
+    query: &HashMap<String, String>
+) -> Response {
+    let by = query.get("sort").map_or("name", |v| v);
+    let desc = query.get("order").is_some_and(|v| v == "desc");
+    sort(&mut entries, by, desc);
+
+    let accept = headers.get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let json = accept.contains("application/json") &&
+        !accept.contains("text/html");
+
+    let (mime, body) = if json {
+        (
+            "application/json",
+            serde_json::to_string(&entries).unwrap_or(String::from("[]"))
+        )
+    } else {
+        ("text/html; charset=utf-8", html(path, &entries, by, desc))
+    };
+
+    let mut response = Response::new(body.into());
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(mime)
+    );
+    // the same url is html or json depending on accept
+    response.headers_mut().insert(VARY, HeaderValue::from_static("accept"));
+    response
+}
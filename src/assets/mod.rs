@@ -0,0 +1,315 @@
+mod conditional;
+mod encoding;
+mod listing;
+mod range;
+mod stream;
+
+use std::error::Error;
+use std::collections::HashMap;
+use std::path::{PathBuf, Path};
+use tokio::fs::metadata;
+use std::time::{SystemTime, UNIX_EPOCH};
+use axum::{
+    body::Body,
+    response::Response,
+    http::{StatusCode, HeaderMap},
+    http::header::{
+        HeaderValue, HeaderName, CONTENT_TYPE, CONTENT_RANGE, ACCEPT_RANGES,
+        CONTENT_LENGTH, CONTENT_ENCODING, ETAG, LAST_MODIFIED, RANGE, VARY,
+        CACHE_CONTROL
+    },
+};
+use glob_match::glob_match;
+use range::Range;
+use crate::config::Cache;
+use crate::debug::debug;
+use crate::errors::failure;
+
+#[derive(Clone)]
+pub struct Assets {
+    all: bool,
+    ignore: Vec<String>,
+    compress: bool,
+    cache: Vec<Cache>,
+    spa: Option<String>,
+    list: bool,
+    dir: PathBuf
+}
+
+impl Assets {
+    pub fn new (
+        dir: PathBuf,
+        all: bool,
+        ignore: Vec<String>,
+        compress: bool,
+        cache: Vec<Cache>,
+        spa: Option<String>,
+        list: bool
+    ) -> Result<Assets, Box<dyn Error>> {
+        let p = dir.as_path();
+        if !p.is_dir() {
+            Err(format!("assets is not a dir: {}", p.display()).into())
+        } else {
+            Ok(Assets {
+                dir,
+                all,
+                ignore,
+                compress,
+                cache,
+                spa,
+                list
+            })
+        }
+    }
+
+    fn visible (&self, dir: &str, name: &str) -> bool {
+        if !self.all && name.starts_with('.') {
+            return false;
+        }
+
+        let path = Path::new(dir).join(name);
+        let path = path.to_str().unwrap_or("");
+        !self.ignore.iter().any(|glob| glob_match(glob, path))
+    }
+
+    // hidden and ignored paths are not found, even when the file exists
+    fn hidden (&self, path: &Path) -> bool {
+        let path_str = path.to_str().unwrap_or("");
+        if self.ignore.iter().any(|glob| glob_match(glob, path_str)) {
+            return true;
+        }
+
+        !self.all && path.components().any(|component| {
+            let name = component.as_os_str().to_str().unwrap_or("");
+            name.is_empty() || (name.len() > 1 && name.as_bytes()[0] == b'.')
+        })
+    }
+
+    fn cache_control (&self, file: &Path) -> &str {
+        let path = match file.strip_prefix(&self.dir) {
+            Ok(path) => format!("/{}", path.to_str().unwrap_or("")),
+            Err(_) => {
+                return "";
+            }
+        };
+
+        for rule in &self.cache {
+            if glob_match(&rule.glob, &path) {
+                return &rule.control;
+            }
+        }
+
+        ""
+    }
+
+    async fn getter (
+        &self,
+        path_str: &str,
+        headers: &HeaderMap,
+        query: &HashMap<String, String>
+    ) -> Result<Response, StatusCode> {
+        let path = Path::new(path_str);
+        let dir = self.dir.as_path();
+        let mut file = dir.join(path);
+        let mut response: Response;
+        let mut list = false;
+
+        if file.is_dir() {
+            let index = file.join("index.html");
+            if self.list && !index.is_file() {
+                list = true;
+            } else {
+                file = index;
+            }
+        }
+
+        if !file.starts_with(dir) || !(list || file.is_file()) {
+            return Err(StatusCode::NOT_FOUND);
+        }
+
+        let path_str = path.to_str().unwrap_or("");
+        if self.hidden(path) {
+            return Err(StatusCode::NOT_FOUND);
+        }
+
+        if list {
+            let entries = listing::entries(&file, |name| {
+                self.visible(path_str, name)
+            }).await?;
+            return Ok(listing::response(path_str, entries, headers, query));
+        }
+
+        let control = self.cache_control(&file);
+        let mime = mime_guess::from_path(&file).first_raw().unwrap_or("");
+        let mut encoding: Option<&str> = None;
+        // the response depends on accept-encoding whenever a precompressed
+        // sibling exists, even when it is not the one served
+        let mut vary = false;
+        if self.compress {
+            let siblings = encoding::siblings(&file).await;
+            vary = !siblings.is_empty();
+            for name in encoding::accepted(headers) {
+                if let Some((_, sibling)) = siblings.iter()
+                    .find(|(sibling, _)| *sibling == name)
+                {
+                    file = sibling.clone();
+                    encoding = Some(name);
+                    break;
+                }
+            }
+        }
+
+        let meta = match metadata(&file).await {
+            Ok(meta) => meta,
+            Err(_) => {
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+        let len = meta.len();
+        let modified = meta.modified().ok();
+        let etag = conditional::etag(len, modified);
+
+        if conditional::not_modified(headers, &etag, modified) {
+            response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            validators(&mut response, &etag, modified);
+            content_encoding(&mut response, encoding, vary);
+            header(&mut response, CACHE_CONTROL, control);
+            return Ok(response);
+        }
+
+        let ranges = if conditional::if_range(headers, &etag, modified) {
+            range::parse(
+                headers.get(RANGE).and_then(|value| value.to_str().ok()),
+                len
+            )
+        } else {
+            Range::Full
+        };
+
+        match ranges {
+            Range::Full => {
+                response = Response::new(body(&file, 0, len).await?);
+                header(&mut response, CONTENT_TYPE, mime);
+                header(&mut response, CONTENT_LENGTH, &len.to_string());
+            },
+            Range::Unsatisfiable => {
+                response = Response::new(Body::empty());
+                *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                header(&mut response, CONTENT_RANGE,
+                    &format!("bytes */{}", len)
+                );
+            },
+            Range::Partial(ranges) => {
+                if ranges.len() == 1 {
+                    let (start, end) = ranges[0];
+                    response = Response::new(
+                        body(&file, start, end - start + 1).await?
+                    );
+                    header(&mut response, CONTENT_TYPE, mime);
+                    header(&mut response, CONTENT_LENGTH,
+                        &(end - start + 1).to_string()
+                    );
+                    header(&mut response, CONTENT_RANGE,
+                        &range::content_range(start, end, len)
+                    );
+                } else {
+                    let boundary = boundary();
+                    let (size, body) = stream::multipart(
+                        file.clone(), ranges, len, mime, &boundary
+                    );
+                    response = Response::new(body);
+                    header(&mut response, CONTENT_TYPE, &format!(
+                        "multipart/byteranges; boundary={}", boundary
+                    ));
+                    header(&mut response, CONTENT_LENGTH, &size.to_string());
+                }
+                *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+            }
+        };
+
+        header(&mut response, ACCEPT_RANGES, "bytes");
+        validators(&mut response, &etag, modified);
+        content_encoding(&mut response, encoding, vary);
+        header(&mut response, CACHE_CONTROL, control);
+
+        Ok(response)
+    }
+
+    pub async fn get (
+        &self,
+        path_str: &str,
+        headers: &HeaderMap,
+        query: &HashMap<String, String>
+    ) -> Response {
+        let path = format!("/{}", path_str);
+        let mut result = self.getter(path_str, headers, query).await;
+        if let (Err(StatusCode::NOT_FOUND), Some(spa)) = (&result, &self.spa) {
+            let path = Path::new(path_str);
+            if path.extension().is_none() && !self.hidden(path) {
+                result = self.getter(spa, headers, query).await;
+            }
+        }
+
+        match result {
+            Ok(response) => {
+                debug("GET", &path, Some(response.status().as_u16()), "");
+                response
+            },
+            Err(status) => {
+                debug("GET", &path, Some(status.as_u16()), "");
+                failure(status, "")
+            }
+        }
+    }
+}
+
+fn header (response: &mut Response, name: HeaderName, value: &str) {
+    if !value.is_empty() {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+}
+
+async fn body (
+    file: &Path,
+    start: u64,
+    len: u64
+) -> Result<Body, StatusCode> {
+    match stream::file(file, start, len).await {
+        Ok(body) => Ok(body),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+fn validators (
+    response: &mut Response,
+    etag: &str,
+    modified: Option<SystemTime>
+) {
+    header(response, ETAG, etag);
+    if let Some(modified) = modified {
+        header(response, LAST_MODIFIED, &conditional::http_date(modified));
+    }
+}
+
+fn content_encoding (
+    response: &mut Response,
+    encoding: Option<&str>,
+    vary: bool
+) {
+    if let Some(encoding) = encoding {
+        header(response, CONTENT_ENCODING, encoding);
+    }
+    if vary {
+        header(response, VARY, "accept-encoding");
+    }
+}
+
+fn boundary () -> String {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => format!("minirps{:x}", duration.as_nanos()),
+        Err(_) => String::from("minirps")
+    }
+}
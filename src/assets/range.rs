@@ -0,0 +1,117 @@
+// more ranges are served as the full file, each part reopens the file
+const MAX_RANGES: usize = 16;
+
+pub enum Range {
+    Full,
+    Partial(Vec<(u64, u64)>),
+    Unsatisfiable
+}
+
+fn spec (value: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let (start, end) = match value.trim().split_once('-') {
+        Some(bounds) => bounds,
+        None => {
+            return Err(());
+        }
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        let suffix: u64 = end.parse().map_err(|_| ())?;
+        if suffix == 0 || len == 0 {
+            Ok(None)
+        } else {
+            Ok(Some((len.saturating_sub(suffix), len - 1)))
+        }
+    } else {
+        let start: u64 = start.parse().map_err(|_| ())?;
+        let end: u64 = if end.is_empty() {
+            u64::MAX
+        } else {
+            end.parse().map_err(|_| ())?
+        };
+        if end < start {
+            Err(())
+        } else if start >= len {
+            Ok(None)
+        } else {
+            Ok(Some((start, end.min(len - 1))))
+        }
+    }
+}
+
+pub fn parse (header: Option<&str>, len: u64) -> Range {
+    let header = match header {
+        Some(header) => header.trim(),
+        None => {
+            return Range::Full;
+        }
+    };
+    let specs = match header.split_once('=') {
+        Some((unit, specs)) if unit.trim().eq_ignore_ascii_case("bytes") => {
+            specs
+        },
+        _ => {
+            return Range::Full;
+        }
+    };
+
+    let specs: Vec<&str> = specs.split(',')
+        .filter(|value| !value.trim().is_empty())
+        .collect();
+    if specs.len() > MAX_RANGES {
+        return Range::Full;
+    }
+
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for value in specs {
+        match spec(value, len) {
+            Ok(Some(range)) => ranges.push(range),
+            Ok(None) => {},
+            Err(_) => {
+                return Range::Full;
+            }
+        }
+    }
+
+    if ranges.is_empty() {
+        return Range::Unsatisfiable;
+    }
+
+    ranges.sort();
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => {
+                last.1 = last.1.max(end);
+            },
+            _ => merged.push((start, end))
+        }
+    }
+
+    Range::Partial(merged)
+}
+
+pub fn content_range (start: u64, end: u64, len: u64) -> String {
+    format!("bytes {}-{}/{}", start, end, len)
+}
+
+pub fn part (
+    start: u64,
+    end: u64,
+    len: u64,
+    mime: &str,
+    boundary: &str
+) -> String {
+    let mut part = format!("\r\n--{}\r\n", boundary);
+    if !mime.is_empty() {
+        part = format!("{}Content-Type: {}\r\n", part, mime);
+    }
+    format!("{}Content-Range: {}\r\n\r\n",
+        part, content_range(start, end, len)
+    )
+}
+
+pub fn closing (boundary: &str) -> String {
+    format!("\r\n--{}--\r\n", boundary)
+}
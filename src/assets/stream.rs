@@ -0,0 +1,58 @@
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use axum::body::{Body, Bytes};
+use futures_util::stream::{self, StreamExt};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use super::range;
+
+async fn open (path: &Path, start: u64) -> io::Result<File> {
+    let mut file = File::open(path).await?;
+    if start > 0 {
+        file.seek(SeekFrom::Start(start)).await?;
+    }
+    Ok(file)
+}
+
+pub async fn file (path: &Path, start: u64, len: u64) -> io::Result<Body> {
+    let file = open(path, start).await?;
+    Ok(Body::from_stream(ReaderStream::new(file.take(len))))
+}
+
+pub fn multipart (
+    path: PathBuf,
+    ranges: Vec<(u64, u64)>,
+    len: u64,
+    mime: &str,
+    boundary: &str
+) -> (u64, Body) {
+    let mut size: u64 = 0;
+    let mut parts: Vec<(Bytes, u64, u64)> = Vec::new();
+    for (start, end) in ranges {
+        let head = range::part(start, end, len, mime, boundary);
+        size += head.len() as u64 + end - start + 1;
+        parts.push((Bytes::from(head), start, end));
+    }
+    let closing = Bytes::from(range::closing(boundary));
+    size += closing.len() as u64;
+
+    let body = stream::iter(parts).then(move |(head, start, end)| {
+        let path = path.clone();
+        async move {
+            let head = stream::once(async move {
+                Ok::<Bytes, io::Error>(head)
+            });
+            match open(&path, start).await {
+                Ok(file) => head.chain(
+                    ReaderStream::new(file.take(end - start + 1))
+                ).left_stream(),
+                Err(err) => head.chain(
+                    stream::once(async move { Err(err) })
+                ).right_stream()
+            }
+        }
+    }).flatten().chain(stream::once(async move { Ok(closing) }));
+
+    (size, Body::from_stream(body))
+}
@@ -0,0 +1,92 @@
+use std::error::Error;
+use std::time::Duration;
+use reqwest::{Client, redirect::Policy};
+use crate::config::HttpClient;
+
+const IDLE_TIMEOUT: u64 = 90;
+const KEEP_ALIVE: u64 = 60;
+const CONNECT_TIMEOUT: u64 = 10;
+const HEADER_TIMEOUT: u64 = 60;
+const FETCH_TIMEOUT: u64 = 30;
+
+// also applied to the requests sent over Unix sockets
+#[derive(Clone, Copy)]
+pub struct Timeouts {
+    pub connect: Duration,
+    pub read: Option<Duration>,
+    // waiting for the response headers, a stream is not cut
+    pub header: Duration
+}
+
+impl Timeouts {
+    pub fn response (&self) -> Duration {
+        match self.read {
+            Some(read) => read.min(self.header),
+            None => self.header
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Clients {
+    // the proxy passes the redirects to the client
+    pub http: Client,
+    // HTTP/2 without negotiation, for the upstreams with http2
+    pub h2c: Client,
+    // the request functions of the templates follow the redirects
+    pub fetch: Client,
+    pub timeouts: Timeouts
+}
+
+fn timeouts (config: Option<&HttpClient>) -> Timeouts {
+    Timeouts {
+        connect: Duration::from_secs(
+            config.and_then(|c| c.connect_timeout).unwrap_or(CONNECT_TIMEOUT)
+        ),
+        read: config.and_then(|c| c.read_timeout).map(Duration::from_secs),
+        header: Duration::from_secs(
+            config.and_then(|c| c.header_timeout).unwrap_or(HEADER_TIMEOUT)
+        )
+    }
+}
+
+fn build (
+    config: Option<&HttpClient>,
+    http2: bool,
+    redirect: Policy,
+    timeout: Option<u64>
+) -> Result<Client, Box<dyn Error>> {
+    let mut builder = Client::builder()
+        .redirect(redirect)
+        .pool_idle_timeout(Duration::from_secs(
+            config.and_then(|c| c.idle_timeout).unwrap_or(IDLE_TIMEOUT)
+        ))
+        .tcp_keepalive(Duration::from_secs(
+            config.and_then(|c| c.keep_alive).unwrap_or(KEEP_ALIVE)
+        ))
+        .connect_timeout(timeouts(config).connect);
+    if let Some(timeout) = timeouts(config).read {
+        builder = builder.read_timeout(timeout);
+    }
+    if let Some(timeout) = config.and_then(|c| c.timeout).or(timeout) {
+        builder = builder.timeout(Duration::from_secs(timeout));
+    }
+    if let Some(max) = config.and_then(|c| c.pool_max_idle) {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if http2 {
+        builder = builder.http2_prior_knowledge();
+    }
+
+    Ok(builder.build()?)
+}
+
+pub fn new (config: Option<&HttpClient>) -> Result<Clients, Box<dyn Error>> {
+    Ok(Clients {
+        http: build(config, false, Policy::none(), None)?,
+        h2c: build(config, true, Policy::none(), None)?,
+        // the templates wait for the whole body, it is always limited
+        fetch: build(config, false, Policy::default(), Some(FETCH_TIMEOUT))?,
+        timeouts: timeouts(config)
+    })
+}
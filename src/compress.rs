@@ -0,0 +1,89 @@
+use axum::response::Response;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::http::header::{CONTENT_TYPE, CONTENT_ENCODING, ETAG};
+use tower_http::compression::{CompressionLayer, CompressionLevel};
+use tower_http::compression::predicate::{Predicate, SizeAbove};
+use glob_match::glob_match;
+use crate::config::Compression;
+
+const MIN_SIZE: u16 = 1024;
+
+const COMPRESSIBLE: [&str; 12] = [
+    "application/javascript",
+    "application/json",
+    "application/wasm",
+    "application/x-javascript",
+    "application/xml",
+    "application/xhtml+xml",
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/manifest+json",
+    "image/svg+xml",
+    "image/x-icon",
+    "font/ttf"
+];
+
+fn compressible (mime: &str) -> bool {
+    mime.starts_with("text/") && mime != "text/event-stream" ||
+    mime.ends_with("+json") ||
+    mime.ends_with("+xml") ||
+    COMPRESSIBLE.contains(&mime)
+}
+
+pub fn layer (
+    config: &Compression
+) -> CompressionLayer<impl Predicate + Send + Sync + 'static> {
+    let exclude = config.exclude.clone().unwrap_or_default();
+    let level = match config.level {
+        Some(level) => CompressionLevel::Precise(level),
+        None => CompressionLevel::Default
+    };
+
+    CompressionLayer::new().quality(level).compress_when(
+        SizeAbove::new(config.min_size.unwrap_or(MIN_SIZE)).and(move |
+            _, _, headers: &HeaderMap, _: &_
+        | -> bool {
+            let mime = headers.get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("");
+            let mime = mime.split(';').next().unwrap_or("").trim()
+                .to_lowercase();
+
+            compressible(&mime) && !exclude.iter().any(|glob| {
+                glob_match(glob, &mime)
+            })
+        })
+    )
+}
+
+// a response already encoded before the compression layer (ex: by the
+// upstream of a proxy or a precompressed asset) keeps its etag
+#[derive(Clone)]
+struct Encoded;
+
+pub async fn encoded (mut response: Response) -> Response {
+    if response.headers().contains_key(CONTENT_ENCODING) {
+        response.extensions_mut().insert(Encoded);
+    }
+    response
+}
+
+pub async fn weak_etag (mut response: Response) -> Response {
+    if !response.headers().contains_key(CONTENT_ENCODING) ||
+        response.extensions().get::<Encoded>().is_some()
+    {
+        return response;
+    }
+
+    let etag = response.headers().get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .filter(|etag| etag.starts_with('"'))
+        .map(|etag| format!("W/{}", etag));
+    if let Some(etag) = etag {
+        if let Ok(etag) = HeaderValue::from_str(&etag) {
+            response.headers_mut().insert(ETAG, etag);
+        }
+    }
+
+    response
+}
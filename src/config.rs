@@ -2,15 +2,122 @@ use toml;
 use serde_json;
 use serde_derive::Deserialize;
 use std::error::Error;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::net::IpAddr;
 use std::ffi::OsStr;
 use std::fs::read_to_string;
 
+const REDIRECTS: [u16; 5] = [301, 302, 303, 307, 308];
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct Route {
     pub method: String,
     pub path: String,
-    pub template: String
+    pub template: String,
+    pub client_cert: Option<bool>,
+    pub stream: Option<bool>
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Listener {
+    pub host: Option<IpAddr>,
+    pub port: u16,
+    pub https: Option<bool>
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Tls {
+    pub names: Vec<String>,
+    pub cert: PathBuf,
+    pub key: PathBuf
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Backend {
+    pub url: String,
+    pub weight: Option<u32>
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Health {
+    pub path: String,
+    pub interval: Option<u64>,
+    pub timeout: Option<u64>,
+    pub status: Option<u16>,
+    pub rise: Option<u32>,
+    pub fall: Option<u32>
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Breaker {
+    pub failures: u32,
+    pub cooldown: Option<u64>
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Upstream {
+    pub backends: Vec<Backend>,
+    pub strategy: Option<String>,
+    pub hash: Option<String>,
+    pub max_fails: Option<u32>,
+    pub fail_timeout: Option<u64>,
+    pub health: Option<Health>,
+    pub timeout: Option<u64>,
+    pub retries: Option<u32>,
+    pub breaker: Option<Breaker>,
+    pub http2: Option<bool>
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct HttpClient {
+    pub pool_max_idle: Option<usize>,
+    pub idle_timeout: Option<u64>,
+    pub keep_alive: Option<u64>,
+    pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    pub header_timeout: Option<u64>,
+    pub timeout: Option<u64>
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct ProxyCache {
+    pub size: Option<usize>,
+    pub max_entry: Option<usize>,
+    pub dir: Option<PathBuf>,
+    pub disk_size: Option<u64>
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Host {
+    pub names: Vec<String>,
+    pub assets: Option<PathBuf>,
+    pub templates: Option<PathBuf>,
+    pub data: Option<PathBuf>,
+    pub routes: Option<Vec<Route>>,
+    pub cors: Option<Vec<String>>,
+    pub errors: Option<HashMap<String, String>>
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Redirect {
+    pub host: Option<IpAddr>,
+    pub port: u16,
+    pub status: Option<u16>,
+    pub exempt: Option<Vec<String>>
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Cache {
+    pub glob: String,
+    pub control: String
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Compression {
+    pub level: Option<i32>,
+    pub min_size: Option<u16>,
+    pub exclude: Option<Vec<String>>
 }
 
 #[derive(Deserialize, Clone, Debug, Default)]
@@ -18,16 +125,42 @@ pub struct Config {
     pub all: Option<bool>,
     pub ignore: Option<Vec<String>>,
     pub cors: Option<Vec<String>>,
+    pub compression: Option<Compression>,
+    pub cache: Option<Vec<Cache>>,
+    pub spa: Option<String>,
+    pub list: Option<bool>,
+    pub watch: Option<bool>,
     pub port: Option<u16>,
+    pub host: Option<IpAddr>,
+    pub listeners: Option<Vec<Listener>>,
+    pub redirect: Option<Redirect>,
     pub cert: Option<PathBuf>,
     pub key: Option<PathBuf>,
+    pub tls: Option<Vec<Tls>>,
+    pub client_ca: Option<PathBuf>,
     pub assets: Option<PathBuf>, 
     pub templates: Option<PathBuf>, 
     pub data: Option<PathBuf>, 
-    pub routes: Option<Vec<Route>>
+    pub routes: Option<Vec<Route>>,
+    pub body_limit: Option<usize>,
+    pub errors: Option<HashMap<String, String>>,
+    pub hosts: Option<Vec<Host>>,
+    pub upstreams: Option<HashMap<String, Upstream>>,
+    pub status: Option<String>,
+    pub http_client: Option<HttpClient>,
+    pub trusted_proxies: Option<Vec<String>>,
+    pub proxy_cache: Option<ProxyCache>
 }
 
 impl Config {
+    // whether any route requires a client certificate
+    pub fn client_cert (&self) -> bool {
+        let hosts = self.hosts.iter().flatten()
+            .flat_map(|host| host.routes.iter().flatten());
+        self.routes.iter().flatten().chain(hosts)
+            .any(|route| route.client_cert.unwrap_or(false))
+    }
+
     pub fn new(file: Option<&Path>) -> Result<Config, Box<dyn Error>> {
         match file {
             None => Ok(Default::default()),
@@ -91,6 +224,46 @@ impl Config {
                     if let Some(key) = config.key {
                         config.key = Some(dir.join(key));
                     }
+                    if let Some(ca) = config.client_ca {
+                        config.client_ca = Some(dir.join(ca));
+                    }
+                    if let Some(hosts) = &mut config.hosts {
+                        for host in hosts {
+                            host.assets = host.assets.as_ref()
+                                .map(|assets| dir.join(assets));
+                            host.templates = host.templates.as_ref()
+                                .map(|templates| dir.join(templates));
+                            host.data = host.data.as_ref()
+                                .map(|data| dir.join(data));
+                        }
+                    }
+                    if let Some(cache) = &mut config.proxy_cache {
+                        cache.dir = cache.dir.as_ref()
+                            .map(|cache| dir.join(cache));
+                    }
+                    if let Some(tls) = &mut config.tls {
+                        for tls in tls {
+                            tls.cert = dir.join(&tls.cert);
+                            tls.key = dir.join(&tls.key);
+                        }
+                    }
+                }
+
+                if let Some(redirect) = &config.redirect {
+                    if let Some(status) = redirect.status {
+                        if !REDIRECTS.contains(&status) {
+                            return Err(format!(
+                                "Redirect status {} must be one of {:?}",
+                                status, REDIRECTS
+                            ).into());
+                        }
+                    }
+                }
+
+                if config.client_cert() && config.client_ca.is_none() {
+                    return Err(
+                        "Routes with client_cert require a client_ca".into()
+                    );
                 }
 
                 Ok(config)
@@ -21,3 +21,7 @@ pub fn debug (
         if error.len() > 0 {format!("\n{}", error)} else {String::new()}
     );
 }
+
+pub fn log (message: &str) {
+    println!("[{}] {}", time_string(Local::now()), message);
+}
@@ -0,0 +1,77 @@
+use std::convert::Infallible;
+use axum::{
+    body::{Body, to_bytes},
+    extract::State,
+    response::{Response, sse::{Event, KeepAlive, Sse}},
+    http::{StatusCode, header::{CONTENT_TYPE, CONTENT_ENCODING, CONTENT_LENGTH}},
+};
+use futures_util::stream::{self, Stream};
+use tokio::sync::broadcast::{self, Sender, error::RecvError};
+
+pub const PATH: &str = "/__minirps/livereload";
+
+const SCRIPT: &str = "<script>new EventSource(\"/__minirps/livereload\")\
+    .onmessage = function () { location.reload(); };</script>";
+
+#[derive(Clone)]
+pub struct LiveReload {
+    tx: Sender<()>
+}
+
+impl LiveReload {
+    pub fn new () -> LiveReload {
+        let (tx, _) = broadcast::channel(16);
+        LiveReload {tx}
+    }
+
+    pub fn reload (&self) {
+        let _ = self.tx.send(());
+    }
+}
+
+pub async fn events (
+    State(live): State<LiveReload>
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = stream::unfold(live.tx.subscribe(), |mut rx| async move {
+        match rx.recv().await {
+            Ok(_) | Err(RecvError::Lagged(_)) => {
+                Some((Ok(Event::default().data("reload")), rx))
+            },
+            Err(RecvError::Closed) => None
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub async fn inject (response: Response) -> Response {
+    let html = response.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|mime| mime.starts_with("text/html"));
+    if !html ||
+        response.status() != StatusCode::OK ||
+        response.headers().contains_key(CONTENT_ENCODING)
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let mut body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body.to_vec(),
+        Err(_) => {
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+
+    let lower = body.to_ascii_lowercase();
+    let end = lower.windows(7).rposition(|tag| tag == b"</body>");
+    match end {
+        Some(end) => {
+            body.splice(end..end, SCRIPT.bytes());
+        },
+        None => body.extend_from_slice(SCRIPT.as_bytes())
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+
+    Response::from_parts(parts, body.into())
+}
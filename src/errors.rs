@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use minijinja::{Environment, context};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    http::{StatusCode, HeaderValue, header::CONTENT_TYPE},
+};
+use crate::debug::debug;
+
+#[derive(Clone)]
+pub struct Failure(pub String);
+
+pub fn failure (status: StatusCode, error: &str) -> Response {
+    let mut response = status.into_response();
+    response.extensions_mut().insert(Failure(error.to_string()));
+    response
+}
+
+#[derive(Clone)]
+pub struct Errors {
+    env: Environment<'static>,
+    pages: HashMap<String, String>
+}
+
+impl Errors {
+    pub fn new (
+        env: &Environment<'static>,
+        pages: HashMap<String, String>
+    ) -> Errors {
+        Errors {
+            env: env.clone(),
+            pages
+        }
+    }
+
+    fn page (&self, status: StatusCode) -> Option<&String> {
+        let code = status.as_u16();
+        self.pages.get(&code.to_string())
+            .or(self.pages.get(&format!("{}xx", code / 100)))
+    }
+
+    fn render (
+        &self,
+        template: &str,
+        status: StatusCode,
+        method: &str,
+        path: &str,
+        error: &str
+    ) -> Result<String, String> {
+        let tpl = match self.env.get_template(template) {
+            Ok(tpl) => tpl,
+            Err(err) => {
+                return Err(format!("{:#}", err));
+            }
+        };
+        match tpl.render(context! {
+            status => status.as_u16(),
+            reason => status.canonical_reason().unwrap_or(""),
+            method => method,
+            path => path,
+            error => error
+        }) {
+            Ok(body) => Ok(body),
+            Err(err) => Err(format!("Fail to render error page!\n{:#}", err))
+        }
+    }
+}
+
+pub async fn handler (
+    State(errors): State<Errors>,
+    request: Request,
+    next: Next
+) -> Response {
+    let method = request.method().as_str().to_string();
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+
+    let error = match response.extensions().get::<Failure>() {
+        Some(Failure(error)) => error.clone(),
+        None => {
+            return response;
+        }
+    };
+    let status = response.status();
+    let template = match errors.page(status) {
+        Some(template) => template,
+        None => {
+            return response;
+        }
+    };
+
+    match errors.render(template, status, &method, &path, &error) {
+        Ok(body) => {
+            let mut response = (status, body).into_response();
+            if let Some(mime) = mime_guess::from_path(template).first_raw() {
+                if let Ok(mime) = HeaderValue::from_str(mime) {
+                    response.headers_mut().insert(CONTENT_TYPE, mime);
+                }
+            }
+            response
+        },
+        Err(err) => {
+            debug(&method, &path, Some(status.as_u16()), &err);
+            status.into_response()
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+use axum::{Router, extract::Request, http::header::HOST};
+use tower::ServiceExt;
+
+// a leading `*.` matches a single label, as in the certificates
+pub fn matches (name: &str, server: &str) -> bool {
+    let name = name.to_lowercase();
+    match name.strip_prefix("*.") {
+        Some(domain) => server.split_once('.')
+            .is_some_and(|(_, parent)| parent == domain),
+        None => name == server
+    }
+}
+
+// a leading `*.` matches subdomains of any depth
+fn site (name: &str, server: &str) -> bool {
+    let name = name.to_lowercase();
+    match name.strip_prefix("*.") {
+        Some(domain) => server.strip_suffix(domain)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => name == server
+    }
+}
+
+fn server (request: &Request) -> Option<String> {
+    let host = match request.headers().get(HOST) {
+        Some(host) => host.to_str().ok()?,
+        None => request.uri().host()?
+    };
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host
+    };
+
+    Some(host.trim_end_matches('.').to_lowercase())
+}
+
+pub fn router (default: Router, hosts: Vec<(Vec<String>, Router)>) -> Router {
+    let hosts = Arc::new(hosts);
+    Router::new().fallback(move |request: Request| {
+        let app = server(&request).and_then(|server| {
+            hosts.iter().find(|(names, _)| {
+                names.iter().any(|name| site(name, &server))
+            })
+        }).map_or(default.clone(), |(_, app)| app.clone());
+        async move {
+            app.oneshot(request).await
+        }
+    })
+}
@@ -3,24 +3,44 @@ mod assets;
 mod config;
 mod app;
 mod debug;
+mod compress;
+mod errors;
+mod reload;
+mod dev;
+mod server;
+mod redirect;
+mod tls;
+mod hosts;
+mod upstream;
+mod client;
+mod unix;
 
 use std::error::Error;
 use std::path::PathBuf;
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use tokio::task::JoinSet;
 use clap::{Parser};
 use tower_http::cors::{Any, CorsLayer};
 use axum::{
-    extract::Path,
+    extract::{Path, Query},
+    middleware::{map_response, from_fn_with_state},
+    Json,
     routing::{get, on, Router},
-    http::{Method, header::{HeaderValue}}
+    http::{Method, StatusCode, HeaderMap, header::{HeaderValue}}
 };
-use axum_server::tls_openssl::OpenSSLConfig;
 use crate::assets::Assets;
 use crate::config::Config;
-use crate::app::{AppState, handler};
+use crate::app::{AppState, ProxyOptions, Cache, handler};
+use crate::errors::Errors;
+use crate::dev::LiveReload;
+use crate::server::Listener;
+use crate::redirect::Redirect;
+use crate::tls::Certificates;
+use crate::upstream::Upstreams;
+use crate::client::Clients;
 
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     /// config file path. (Accept: .json, .toml)
@@ -35,6 +55,14 @@ struct Cli {
     #[clap(short, long)]
     port: Option<u16>,
 
+    /// IP address to bind the server to (IPv4 or IPv6).
+    #[clap(short='H', long)]
+    host: Option<IpAddr>,
+
+    /// port number of an http server that redirects to https.
+    #[clap(short, long)]
+    redirect: Option<u16>,
+
     /// public key file path.
     #[clap(short, long)]
     cert: Option<PathBuf>,
@@ -54,16 +82,54 @@ struct Cli {
     /// ignore files based on glob match
     #[clap(short, long)]
     ignore: Option<String>,
+
+    /// single page application, fallback file for unmatched paths.
+    #[clap(
+        short,
+        long,
+        num_args=0..=1,
+        require_equals=true,
+        default_missing_value="index.html"
+    )]
+    spa: Option<String>,
+
+    /// list directory contents when there is no index.html
+    #[clap(short, long)]
+    list: bool,
+
+    /// compress responses (gzip, brotli, zstd)
+    #[clap(short='z', long)]
+    compress: bool,
+
+    /// reload templates and config file when they change
+    #[clap(short, long)]
+    watch: bool,
+
+    /// development mode, reload the browser when files change
+    #[clap(short, long)]
+    dev: bool,
 }
 
-fn init () -> Result<(Router, u16, Option<OpenSSLConfig>), Box<dyn Error>> {
-    let cli = Cli::parse();
-    let config = Config::new(cli.config.as_deref())?;
+fn site (
+    cli: &Cli,
+    config: Config,
+    live: &Option<LiveReload>,
+    proxy: &ProxyOptions
+) -> Result<Router, Box<dyn Error>> {
+    let cli = cli.clone();
     let mut app = Router::new();
+    let compression = if cli.compress {
+        Some(config.compression.clone().unwrap_or_default())
+    } else {
+        config.compression.clone()
+    };
 
     if let Some(assets) = cli.assets.or(config.assets) {
         let mut ignore: Vec<String> = Vec::new();
-        let has_home = assets.as_path().join("index.html").is_file();
+        let spa = cli.spa.or(config.spa);
+        let list = cli.list || config.list.unwrap_or(false);
+        let has_home = assets.as_path().join("index.html").is_file() ||
+            spa.is_some() || list;
         if let Some(glob) = cli.ignore {
             ignore.push(glob);
             if let Some(globs) = config.ignore {
@@ -74,34 +140,79 @@ fn init () -> Result<(Router, u16, Option<OpenSSLConfig>), Box<dyn Error>> {
         let loader = Assets::new(
             assets,
             cli.all || config.all.unwrap_or(false),
-            ignore
+            ignore,
+            compression.is_some(),
+            config.cache.unwrap_or_default(),
+            spa,
+            list
         )?;
         if has_home {
             let loader2 = loader.clone();
-            app = app.route("/", get(|| async move {
-                loader2.get("")
+            app = app.route("/", get(|
+                Query(vars): Query<HashMap<String, String>>,
+                headers: HeaderMap
+            | async move {
+                loader2.get("", &headers, &vars).await
             }));
         }
         app = app.route("/*file", get(|
             Path(params): Path<HashMap<String, String>>,
+            Query(vars): Query<HashMap<String, String>>,
+            headers: HeaderMap
         | async move {
-            loader.get(params.get("file").map_or("", |v| v))
+            let file = params.get("file").map_or("", |v| v);
+            loader.get(file, &headers, &vars).await
         }));
     }
 
-    if let (Some(templates), Some(routes)) = (
-        config.templates, config.routes
-    ) {
-        let env = templates::new(templates, config.data)?;
-        for route in &routes {
-            app = app.route(&route.path, on(
-                Method::from_bytes(route.method.as_bytes())?.try_into()?,
-                handler
-            ).with_state(AppState::new(&env, &route.template)));
+    if let Some(path) = &config.status {
+        let upstreams = proxy.upstreams.clone();
+        app = app.route(path, get(|| async move {
+            Json(upstreams.status())
+        }));
+    }
+
+    if let Some(templates) = config.templates {
+        let env = templates::new(
+            templates,
+            config.data,
+            &proxy.upstreams,
+            &proxy.clients.fetch
+        )?;
+        if let Some(routes) = config.routes {
+            for route in &routes {
+                app = app.route(&route.path, on(
+                    Method::from_bytes(route.method.as_bytes())?.try_into()?,
+                    handler
+                ).with_state(AppState::new(
+                    &env,
+                    &route.template,
+                    route.client_cert.unwrap_or(false),
+                    route.stream.unwrap_or(false),
+                    config.body_limit,
+                    proxy
+                )));
+            }
+        }
+
+        if let Some(pages) = config.errors {
+            app = app.fallback(|| async {
+                errors::failure(StatusCode::NOT_FOUND, "")
+            }).layer(from_fn_with_state(
+                Errors::new(&env, pages),
+                errors::handler
+            ));
         }
+    } else if config.errors.is_some() {
+        return Err("The errors pages require the templates".into());
     }
 
 
+    if let Some(live) = live {
+        app = app.layer(map_response(dev::inject))
+            .route(dev::PATH, get(dev::events).with_state(live.clone()));
+    }
+
     let cors = if cli.allow_cors {Some(Vec::new())} else {config.cors};
     if let Some(origins) = cors {
         let mut layer = CorsLayer::new().allow_methods(Any);
@@ -116,47 +227,236 @@ fn init () -> Result<(Router, u16, Option<OpenSSLConfig>), Box<dyn Error>> {
         app = app.layer(layer);
     }
 
-    let port = cli.port.unwrap_or(config.port.unwrap_or(3000));
-
-    let mut ssl: Option<OpenSSLConfig> = None;
-    if let (Some(cert), Some(key)) = (
-        cli.cert.or(config.cert), cli.key.or(config.key)
-    ) {
-        ssl = Some(OpenSSLConfig::from_pem_file(cert, key)?);
+    if let Some(compression) = compression {
+        app = app.layer(map_response(compress::encoded))
+            .layer(compress::layer(&compression))
+            .layer(map_response(compress::weak_etag));
     }
 
-    Ok((app, port, ssl))
+    Ok(app)
 }
 
-#[tokio::main]
-async fn main() -> () {
-    let (app, port, ssl) = match init() {
-        Ok(server) => server,
-        Err(err) => {
-            println!("{}", err);
-            return ();
+// the state of the upstreams and the proxy cache, kept across the reloads
+// while their config is the same
+#[derive(Default)]
+struct Kept {
+    upstreams: Option<(Option<HashMap<String, config::Upstream>>, Upstreams)>,
+    cache: Option<(Option<config::ProxyCache>, Option<Cache>)>
+}
+
+fn router (
+    cli: &Cli,
+    config: Config,
+    live: &Option<LiveReload>,
+    clients: &Clients,
+    kept: &mut Kept
+) -> Result<Router, Box<dyn Error>> {
+    let upstreams = match &kept.upstreams {
+        Some((previous, upstreams)) if *previous == config.upstreams => {
+            upstreams.clone()
+        },
+        _ => match &config.upstreams {
+            Some(upstreams) => Upstreams::new(upstreams)?,
+            None => Upstreams::default()
         }
     };
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
-
-    let server = match ssl {
-        Some(ssl) => {
-            println!("Server started at https://localhost:{}", port);
-            axum_server::bind_openssl(addr, ssl)
-                .serve(app.into_make_service()).await
+    let cache = match &kept.cache {
+        Some((previous, cache)) if *previous == config.proxy_cache => {
+            cache.clone()
         },
+        _ => config.proxy_cache.as_ref().map(Cache::new)
+    };
+    let proxy = ProxyOptions::new(
+        upstreams.clone(),
+        clients,
+        &config.trusted_proxies.clone().unwrap_or_default(),
+        cache.clone()
+    )?;
+    kept.upstreams = Some((config.upstreams.clone(), upstreams));
+    kept.cache = Some((config.proxy_cache.clone(), cache));
+    let hosts = match &config.hosts {
+        Some(hosts) => hosts.clone(),
         None => {
-            println!("Server started at http://localhost:{}", port);
-            axum_server::bind(addr)
-                .serve(app.into_make_service()).await
+            return site(cli, config, live, &proxy);
+        }
+    };
+
+    let mut sites: Vec<(Vec<String>, Router)> = Vec::new();
+    for host in hosts {
+        let cli = Cli {
+            assets: None,
+            ..cli.clone()
+        };
+        let config = Config {
+            assets: host.assets,
+            templates: host.templates,
+            data: host.data,
+            routes: host.routes,
+            cors: host.cors,
+            errors: host.errors,
+            ..config.clone()
+        };
+        sites.push((host.names, site(&cli, config, live, &proxy)?));
+    }
+
+    Ok(hosts::router(site(cli, config, live, &proxy)?, sites))
+}
+
+fn watched (cli: &Cli, config: &Config) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    if let Some(file) = &cli.config {
+        paths.push(file.clone());
+    }
+    if let Some(templates) = &config.templates {
+        paths.push(templates.clone());
+    }
+    if cli.dev {
+        if let Some(assets) = cli.assets.as_ref().or(config.assets.as_ref()) {
+            paths.push(assets.clone());
+        }
+    }
+    for host in config.hosts.iter().flatten() {
+        if let Some(templates) = &host.templates {
+            paths.push(templates.clone());
+        }
+        if cli.dev {
+            if let Some(assets) = &host.assets {
+                paths.push(assets.clone());
+            }
+        }
+    }
+    paths
+}
+
+fn init () -> Result<Vec<(Listener, Router)>, Box<dyn Error>> {
+    let cli = Cli::parse();
+    let config = Config::new(cli.config.as_deref())?;
+    let client_cert = config.client_cert();
+    let live = if cli.dev {Some(LiveReload::new())} else {None};
+    let clients = client::new(config.http_client.as_ref())?;
+    let mut kept = Kept::default();
+    let mut app = router(&cli, config.clone(), &live, &clients, &mut kept)?;
+
+    if cli.watch || cli.dev || config.watch.unwrap_or(false) {
+        let paths = watched(&cli, &config);
+        let cli = cli.clone();
+        let notify = live.clone();
+        app = reload::watch(app, paths, move || {
+            let config = Config::new(cli.config.as_deref())?;
+            let paths = watched(&cli, &config);
+            let app = router(&cli, config, &live, &clients, &mut kept)?;
+            Ok((app, paths))
+        }, move || {
+            if let Some(live) = &notify {
+                live.reload();
+            }
+        })?;
+    }
+
+    let certificates = Certificates::new(
+        cli.cert.or(config.cert),
+        cli.key.or(config.key),
+        config.tls.unwrap_or_default(),
+        config.client_ca
+    );
+    let ssl = certificates.load()?;
+    if let Some(ssl) = &ssl {
+        certificates.watch(ssl.clone())?;
+    }
+
+    let default_host = IpAddr::from([127, 0, 0, 1]);
+    let mut listeners: Vec<Listener> = Vec::new();
+    match config.listeners {
+        Some(configs) if cli.port.is_none() && cli.host.is_none() => {
+            for listener in configs {
+                let addr = SocketAddr::new(
+                    listener.host.or(config.host).unwrap_or(default_host),
+                    listener.port
+                );
+                let ssl = if listener.https.unwrap_or(false) {
+                    match &ssl {
+                        Some(ssl) => Some(ssl.clone()),
+                        None => {
+                            return Err(format!(
+                                "Listener {} requires a certificate for https",
+                                addr
+                            ).into());
+                        }
+                    }
+                } else {
+                    None
+                };
+                listeners.push(Listener {addr, ssl});
+            }
+        },
+        _ => {
+            listeners.push(Listener {
+                addr: SocketAddr::new(
+                    cli.host.or(config.host).unwrap_or(default_host),
+                    cli.port.unwrap_or(config.port.unwrap_or(3000))
+                ),
+                ssl
+            });
         }
+    }
+
+    if client_cert && !listeners.iter().any(|l| l.ssl.is_some()) {
+        return Err("Routes with client_cert require an https listener".into());
+    }
+
+    let redirect = match (cli.redirect, config.redirect) {
+        (Some(port), redirect) => Some(config::Redirect {
+            port,
+            ..redirect.unwrap_or(config::Redirect {
+                host: None,
+                port,
+                status: None,
+                exempt: None
+            })
+        }),
+        (None, redirect) => redirect
     };
+    let mut servers: Vec<(Listener, Router)> = Vec::new();
+    if let Some(redirect) = redirect {
+        let port = match listeners.iter().find(|l| l.ssl.is_some()) {
+            Some(listener) => listener.addr.port(),
+            None => {
+                return Err("Redirect requires an https listener".into());
+            }
+        };
+        servers.push((Listener {
+            addr: SocketAddr::new(
+                redirect.host.or(cli.host).or(config.host)
+                    .unwrap_or(default_host),
+                redirect.port
+            ),
+            ssl: None
+        }, Redirect::new(
+            port,
+            StatusCode::from_u16(redirect.status.unwrap_or(308))?,
+            redirect.exempt.unwrap_or_default()
+        ).router(app.clone())));
+    }
+    for listener in listeners {
+        servers.push((listener, app.clone()));
+    }
+
+    Ok(servers)
+}
 
-    match server {
-        Ok(_) => (),
+#[tokio::main]
+async fn main() -> () {
+    let listeners = match init() {
+        Ok(listeners) => listeners,
         Err(err) => {
-            println!("Fail to start server!\n{}", err.to_string());
-            ()
+            println!("{}", err);
+            return ();
         }
+    };
+
+    let mut servers = JoinSet::new();
+    for (listener, app) in listeners {
+        servers.spawn(listener.serve(app));
     }
+    while servers.join_next().await.is_some() {}
 }
@@ -0,0 +1,81 @@
+use axum::{
+    Router,
+    extract::Request,
+    response::IntoResponse,
+    http::{StatusCode, HeaderValue, header::{HOST, LOCATION}},
+};
+use tower::ServiceExt;
+use glob_match::glob_match;
+use crate::debug::debug;
+
+#[derive(Clone)]
+pub struct Redirect {
+    port: u16,
+    status: StatusCode,
+    exempt: Vec<String>
+}
+
+impl Redirect {
+    pub fn new (
+        port: u16,
+        status: StatusCode,
+        exempt: Vec<String>
+    ) -> Redirect {
+        Redirect {
+            port,
+            status,
+            exempt
+        }
+    }
+
+    fn location (&self, request: &Request) -> Option<String> {
+        let host = match request.headers().get(HOST) {
+            Some(host) => host.to_str().ok()?.to_string(),
+            None => request.uri().authority()?.to_string()
+        };
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => name.to_string(),
+            _ => host
+        };
+        let path = request.uri().path_and_query()
+            .map_or("/", |path| path.as_str());
+
+        if self.port == 443 {
+            Some(format!("https://{}{}", host, path))
+        } else {
+            Some(format!("https://{}:{}{}", host, self.port, path))
+        }
+    }
+
+    pub fn router (self, app: Router) -> Router {
+        Router::new().fallback(move |request: Request| {
+            let redirect = self.clone();
+            let app = app.clone();
+            async move {
+                let path = request.uri().path();
+                if redirect.exempt.iter().any(|glob| glob_match(glob, path)) {
+                    return app.oneshot(request).await.into_response();
+                }
+
+                let method = request.method().to_string();
+                let url = request.uri().to_string();
+                match redirect.location(&request).and_then(|location| {
+                    HeaderValue::from_str(&location).ok()
+                }) {
+                    Some(location) => {
+                        let status = redirect.status;
+                        debug(&method, &url, Some(status.as_u16()), "");
+                        let mut response = status.into_response();
+                        response.headers_mut().insert(LOCATION, location);
+                        response
+                    },
+                    None => {
+                        let status = StatusCode::BAD_REQUEST;
+                        debug(&method, &url, Some(status.as_u16()), "");
+                        status.into_response()
+                    }
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,172 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, mpsc::channel};
+use std::thread;
+use std::time::Duration;
+use axum::{Router, extract::Request};
+use tower::ServiceExt;
+use notify::{Event, EventKind, RecursiveMode, Watcher, recommended_watcher};
+use notify::event::ModifyKind;
+use crate::debug::log;
+
+struct Target {
+    watch: PathBuf,
+    mode: RecursiveMode,
+    path: PathBuf
+}
+
+impl Target {
+    fn new (path: &Path) -> Vec<Target> {
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(_) => return Vec::new()
+        };
+        if canonical.is_dir() {
+            return vec![Target {
+                watch: canonical.clone(),
+                mode: RecursiveMode::Recursive,
+                path: canonical
+            }];
+        }
+
+        // the configured path is watched as is, so a symlink that is
+        // replaced (ex: certbot live/ -> archive/) is noticed
+        let path = match std::path::absolute(path) {
+            Ok(path) => path,
+            Err(_) => return Vec::new()
+        };
+        let mut targets: Vec<Target> = Vec::new();
+        for path in [path, canonical] {
+            if targets.iter().any(|target| target.path == path) {
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                targets.push(Target {
+                    watch: parent.to_path_buf(),
+                    mode: RecursiveMode::NonRecursive,
+                    path
+                });
+            }
+        }
+        targets
+    }
+
+    fn matches (&self, path: &Path) -> bool {
+        path.starts_with(&self.path)
+    }
+}
+
+fn changed (event: &Event) -> bool {
+    match event.kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        _ => false
+    }
+}
+
+fn targets (
+    watcher: &mut dyn Watcher,
+    paths: &[PathBuf]
+) -> Vec<Target> {
+    let mut targets: Vec<Target> = Vec::new();
+    for path in paths {
+        let found = Target::new(path);
+        if found.is_empty() {
+            log(&format!("Unable to watch <{}>", path.display()));
+        }
+        for target in found {
+            match watcher.watch(&target.watch, target.mode) {
+                Ok(_) => targets.push(target),
+                Err(err) => log(&format!(
+                    "Unable to watch <{}>\n{:#}", path.display(), err
+                ))
+            }
+        }
+    }
+    targets
+}
+
+pub fn observe<F> (
+    paths: Vec<PathBuf>,
+    mut update: F
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut() -> Option<Vec<PathBuf>> + Send + 'static
+{
+    let (tx, rx) = channel();
+    let mut watcher = recommended_watcher(tx)?;
+    let mut watching = targets(&mut watcher, &paths);
+
+    thread::spawn(move || {
+        while let Ok(result) = rx.recv() {
+            let event = match result {
+                Ok(event) => event,
+                Err(err) => {
+                    log(&format!("Watch error!\n{:#}", err));
+                    continue;
+                }
+            };
+            if !changed(&event) || !event.paths.iter().any(|path| {
+                watching.iter().any(|target| target.matches(path))
+            }) {
+                continue;
+            }
+            while rx.recv_timeout(Duration::from_millis(200)).is_ok() {}
+
+            if let Some(paths) = update() {
+                for target in &watching {
+                    let _ = watcher.unwatch(&target.watch);
+                }
+                watching = targets(&mut watcher, &paths);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+pub fn watch<F, N> (
+    app: Router,
+    paths: Vec<PathBuf>,
+    mut build: F,
+    notify: N
+) -> Result<Router, Box<dyn Error>>
+where
+    F: FnMut() -> Result<(Router, Vec<PathBuf>), Box<dyn Error>> +
+        Send + 'static,
+    N: Fn() + Send + 'static
+{
+    let current = Arc::new(RwLock::new(app));
+    let shared = current.clone();
+
+    observe(paths, move || {
+        match build() {
+            Ok((app, paths)) => {
+                match shared.write() {
+                    Ok(mut current) => *current = app,
+                    Err(err) => *err.into_inner() = app
+                }
+                log("Reloaded!");
+                notify();
+                Some(paths)
+            },
+            Err(err) => {
+                log(&format!("Fail to reload, keeping the last config!\n{}",
+                    err
+                ));
+                None
+            }
+        }
+    })?;
+
+    Ok(Router::new().fallback(move |request: Request| {
+        let app = match current.read() {
+            Ok(app) => app.clone(),
+            Err(err) => err.into_inner().clone()
+        };
+        async move {
+            app.oneshot(request).await
+        }
+    }))
+}
@@ -0,0 +1,38 @@
+use std::net::SocketAddr;
+use axum::Router;
+use axum_server::tls_openssl::OpenSSLConfig;
+use crate::tls::Acceptor;
+
+pub struct Listener {
+    pub addr: SocketAddr,
+    pub ssl: Option<OpenSSLConfig>
+}
+
+impl Listener {
+    fn url (&self) -> String {
+        let scheme = if self.ssl.is_some() {"https"} else {"http"};
+        if self.addr.ip().is_loopback() && self.addr.is_ipv4() {
+            format!("{}://localhost:{}", scheme, self.addr.port())
+        } else {
+            format!("{}://{}", scheme, self.addr)
+        }
+    }
+
+    pub async fn serve (self, app: Router) {
+        println!("Server started at {}", self.url());
+        let service = app.into_make_service_with_connect_info::<SocketAddr>();
+        let server = match self.ssl {
+            Some(ssl) => {
+                axum_server::bind(self.addr).acceptor(Acceptor::new(ssl))
+                    .serve(service).await
+            },
+            None => {
+                axum_server::bind(self.addr).serve(service).await
+            }
+        };
+
+        if let Err(err) = server {
+            println!("Fail to start server at {}!\n{}", self.addr, err);
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use minijinja::Value;
-use reqwest::{Method, Url};
-use reqwest::blocking::{Client, Response};
+use reqwest::{Client, Method, Response, Url};
 use serde_derive::Serialize;
 use std::collections::HashMap;
 use crate::debug::debug;
+use tokio::runtime::Handle;
 use tokio::task::block_in_place;
 
 #[derive(Serialize)]
@@ -14,7 +14,7 @@ struct Res {
 }
 
 impl Res {
-    fn new (response: Response) -> Value {
+    async fn new (response: Response) -> Value {
         let status = response.status().as_u16();
 
         let mut headers = HashMap::new();
@@ -26,7 +26,7 @@ impl Res {
             }
         }
 
-        match response.bytes() {
+        match response.bytes().await {
             Ok(body) => Value::from_serialize(Res {
                 status,
                 headers,
@@ -49,72 +49,58 @@ impl Res {
     }
 }
 
-fn fetch (
-    method: &str,
-    url: &str,
-    body: Option<&Vec<u8>> 
-) -> Value {
-    let method: Method = match method.parse() {
-        Ok(method) => method,
-        Err(err) => {
-            return Res::err(format!("Invalid method!\n{:#?}", err));
-        }
-    };
+#[derive(Clone)]
+pub struct Fetch {
+    client: Client
+}
 
-    let url: Url = match url.parse() {
-        Ok(url) => url,
-        Err(err) => {
-            return Res::err(format!("Invalid URL!\n{:#?}", err));
+impl Fetch {
+    pub fn new (client: &Client) -> Fetch {
+        Fetch {
+            client: client.clone()
         }
-    };
-
-    let m = method.as_str().to_string();
-    let p = url.to_string();
-    let mut request = Client::new().request(method, url);
-    if let Some(body) = body {
-        request = request.body(body.to_vec());
     }
 
-    debug(&m, &p, None, "");
-    block_in_place(move || {
-        match request.send() {
-            Ok(response) => {
-                debug(&m, &p, Some(200), "");
-                Res::new(response)
-            },
+    pub fn fetch (
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<&Vec<u8>> 
+    ) -> Value {
+        let method: Method = match method.parse() {
+            Ok(method) => method,
             Err(err) => {
-                let error = err.to_string();
-                debug(&m, &p, Some(500), &error);
-                Res::err(format!("Request fail!\n{}", &error))
+                return Res::err(format!("Invalid method!\n{:#?}", err));
             }
-        }
-    })
-}
-
-pub fn get (url: &str) -> Value {
-    fetch("GET", url, None)
-}
-
-pub fn delete (url: &str) -> Value {
-    fetch("DELETE", url, None)
-}
-
-pub fn head (url: &str) -> Value {
-    fetch("HEAD", url, None)
-}
+        };
 
-pub fn options (url: &str) -> Value {
-    fetch("OPTIONS", url, None)
-}
-
-pub fn post (url: &str, body: &Vec<u8>) -> Value {
-    fetch("POST", url, Some(body))
-}
+        let url: Url = match url.parse() {
+            Ok(url) => url,
+            Err(err) => {
+                return Res::err(format!("Invalid URL!\n{:#?}", err));
+            }
+        };
 
-pub fn put (url: &str, body: &Vec<u8>) -> Value {
-    fetch("PUT", url, Some(body))
-}
+        let m = method.as_str().to_string();
+        let p = url.to_string();
+        let mut request = self.client.request(method, url);
+        if let Some(body) = body {
+            request = request.body(body.to_vec());
+        }
 
-pub fn patch (url: &str, body: &Vec<u8>) -> Value {
-    fetch("PATCH", url, Some(body))
+        debug(&m, &p, None, "");
+        block_in_place(move || Handle::current().block_on(async move {
+            match request.send().await {
+                Ok(response) => {
+                    debug(&m, &p, Some(response.status().as_u16()), "");
+                    Res::new(response).await
+                },
+                Err(err) => {
+                    let error = err.to_string();
+                    debug(&m, &p, Some(500), &error);
+                    Res::err(format!("Request fail!\n{}", &error))
+                }
+            }
+        }))
+    }
 }
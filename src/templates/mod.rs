@@ -10,12 +10,16 @@ use parse::parse;
 use format::{format, bytes};
 use command::command;
 use file::IO;
-use fetch::{get, delete, head, options, post, put, patch};
+use fetch::Fetch;
 use std::path::{PathBuf};
+use reqwest::Client;
+use crate::upstream::Upstreams;
 
 pub fn new (
     dir: PathBuf,
-    data: Option<PathBuf>
+    data: Option<PathBuf>,
+    upstreams: &Upstreams,
+    client: &Client
 ) -> Result<Environment<'static>, Box<dyn Error>> {
     let mut env = Environment::new();
 
@@ -46,13 +50,32 @@ pub fn new (
             io3.remove(entry)
         });
     }
-    env.add_function("get", get);
-    env.add_function("delete", delete);
-    env.add_function("head", head);
-    env.add_function("options", options);
-    env.add_function("post", post);
-    env.add_function("put", put);
-    env.add_function("patch", patch);
+    let fetch = Fetch::new(client);
+    for (name, method) in [
+        ("get", "GET"),
+        ("delete", "DELETE"),
+        ("head", "HEAD"),
+        ("options", "OPTIONS")
+    ] {
+        let fetch = fetch.clone();
+        env.add_function(name, move |url: &str| -> Value {
+            fetch.fetch(method, url, None)
+        });
+    }
+    for (name, method) in [
+        ("post", "POST"),
+        ("put", "PUT"),
+        ("patch", "PATCH")
+    ] {
+        let fetch = fetch.clone();
+        env.add_function(name, move |url: &str, body: &Vec<u8>| -> Value {
+            fetch.fetch(method, url, Some(body))
+        });
+    }
+    let upstreams = upstreams.clone();
+    env.add_function("upstreams", move || -> Value {
+        Value::from_serialize(upstreams.status())
+    });
 
     Ok(env)
 }
@@ -0,0 +1,269 @@
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use serde_derive::Serialize;
+use openssl::hash::MessageDigest;
+use openssl::ssl::{
+    self, AlpnError, NameType, SniError, SslAcceptor, SslAcceptorBuilder,
+    SslContext, SslFiletype, SslMethod, SslRef, SslVerifyMode,
+};
+use openssl::x509::{X509Name, X509NameRef, X509VerifyResult};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_openssl::SslStream;
+use axum::{Extension, middleware::AddExtension};
+use tower::Layer;
+use axum_server::accept::Accept;
+use axum_server::tls_openssl::{OpenSSLAcceptor, OpenSSLConfig};
+use crate::config::Tls;
+use crate::reload::observe;
+use crate::hosts::matches;
+use crate::debug::log;
+
+fn alpn<'a> (_: &mut SslRef, client: &'a [u8]) -> Result<&'a [u8], AlpnError> {
+    ssl::select_next_proto(b"\x02h2\x08http/1.1", client)
+        .ok_or(AlpnError::NOACK)
+}
+
+fn load (
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>
+) -> Result<SslAcceptorBuilder, Box<dyn Error>> {
+    let build = || -> Result<SslAcceptorBuilder, Box<dyn Error>> {
+        let mut builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls())?;
+        builder.set_certificate_chain_file(cert)?;
+        builder.set_private_key_file(key, SslFiletype::PEM)?;
+        builder.check_private_key()?;
+        builder.set_alpn_select_callback(alpn);
+        Ok(builder)
+    };
+
+    let mut builder = match build() {
+        Ok(builder) => builder,
+        Err(err) => {
+            return Err(format!(
+                "Unable to load certificate <{}> with key <{}>\n{}",
+                cert.display(), key.display(), err
+            ).into());
+        }
+    };
+
+    if let Some(ca) = client_ca {
+        let verify = |builder: &mut SslAcceptorBuilder| {
+            builder.set_ca_file(ca)?;
+            builder.set_client_ca_list(X509Name::load_client_ca_file(ca)?);
+            builder.set_session_id_context(b"minirps")?;
+            builder.set_verify(SslVerifyMode::PEER);
+            Ok::<(), Box<dyn Error>>(())
+        };
+        if let Err(err) = verify(&mut builder) {
+            return Err(format!(
+                "Unable to load client CA <{}>\n{}", ca.display(), err
+            ).into());
+        }
+    }
+
+    Ok(builder)
+}
+
+fn acceptor (
+    default: Option<(&Path, &Path)>,
+    certs: &[Tls],
+    client_ca: Option<&Path>
+) -> Result<Option<SslAcceptor>, Box<dyn Error>> {
+    let mut builder = match (default, certs.first()) {
+        (Some((cert, key)), _) => load(cert, key, client_ca)?,
+        (None, Some(tls)) => load(&tls.cert, &tls.key, client_ca)?,
+        (None, None) => {
+            return Ok(None);
+        }
+    };
+
+    let mut contexts: Vec<(Vec<String>, SslContext)> = Vec::new();
+    for tls in certs {
+        let context = load(&tls.cert, &tls.key, client_ca)?
+            .build().into_context();
+        contexts.push((tls.names.clone(), context));
+    }
+    if !contexts.is_empty() {
+        builder.set_servername_callback(move |ssl, _| {
+            let server = match ssl.servername(NameType::HOST_NAME) {
+                Some(server) => server.to_lowercase(),
+                None => {
+                    return Ok(());
+                }
+            };
+            let context = contexts.iter().find(|(names, _)| {
+                names.iter().any(|name| matches(name, &server))
+            });
+            match context {
+                Some((_, context)) => match ssl.set_ssl_context(context) {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(SniError::ALERT_FATAL)
+                },
+                None => Ok(())
+            }
+        });
+    }
+
+    Ok(Some(builder.build()))
+}
+
+#[derive(Clone)]
+pub struct Certificates {
+    default: Option<(PathBuf, PathBuf)>,
+    certs: Vec<Tls>,
+    client_ca: Option<PathBuf>
+}
+
+impl Certificates {
+    pub fn new (
+        cert: Option<PathBuf>,
+        key: Option<PathBuf>,
+        certs: Vec<Tls>,
+        client_ca: Option<PathBuf>
+    ) -> Certificates {
+        Certificates {
+            default: match (cert, key) {
+                (Some(cert), Some(key)) => Some((cert, key)),
+                _ => None
+            },
+            certs,
+            client_ca
+        }
+    }
+
+    fn acceptor (&self) -> Result<Option<SslAcceptor>, Box<dyn Error>> {
+        let default = self.default.as_ref()
+            .map(|(cert, key)| (cert.as_path(), key.as_path()));
+        acceptor(default, &self.certs, self.client_ca.as_deref())
+    }
+
+    pub fn load (&self) -> Result<Option<OpenSSLConfig>, Box<dyn Error>> {
+        Ok(self.acceptor()?.map(|acceptor| {
+            OpenSSLConfig::from_acceptor(Arc::new(acceptor))
+        }))
+    }
+
+    fn files (&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = Vec::new();
+        if let Some((cert, key)) = &self.default {
+            files.push(cert.clone());
+            files.push(key.clone());
+        }
+        for tls in &self.certs {
+            files.push(tls.cert.clone());
+            files.push(tls.key.clone());
+        }
+        if let Some(ca) = &self.client_ca {
+            files.push(ca.clone());
+        }
+        files
+    }
+
+    fn reload (&self, ssl: &OpenSSLConfig) {
+        match self.acceptor() {
+            Ok(Some(acceptor)) => {
+                ssl.reload_from_acceptor(Arc::new(acceptor));
+                log("Certificates reloaded!");
+            },
+            Ok(None) => (),
+            Err(err) => log(&format!(
+                "Fail to reload certificates, keeping the last ones!\n{}",
+                err
+            ))
+        }
+    }
+
+    pub fn watch (self, ssl: OpenSSLConfig) -> Result<(), Box<dyn Error>> {
+        let certs = self.clone();
+        let config = ssl.clone();
+        // the files are resolved again, following the rotated symlinks
+        observe(self.files(), move || {
+            certs.reload(&config);
+            Some(certs.files())
+        })?;
+
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut hangup = signal(SignalKind::hangup())?;
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    self.reload(&ssl);
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct Client {
+    pub subject: String,
+    pub issuer: String,
+    pub fingerprint: String
+}
+
+fn name (name: &X509NameRef) -> String {
+    name.entries().filter_map(|entry| {
+        let key = entry.object().nid().short_name().ok()?;
+        let value = entry.data().as_utf8().ok()?;
+        Some(format!("{}={}", key, value))
+    }).collect::<Vec<String>>().join(", ")
+}
+
+impl Client {
+    fn new (ssl: &SslRef) -> Option<Client> {
+        let cert = ssl.peer_certificate()?;
+        if ssl.verify_result() != X509VerifyResult::OK {
+            return None;
+        }
+        let digest = cert.digest(MessageDigest::sha256()).ok()?;
+
+        Some(Client {
+            subject: name(cert.subject_name()),
+            issuer: name(cert.issuer_name()),
+            fingerprint: digest.iter().map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<String>>().join(":")
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Acceptor {
+    inner: OpenSSLAcceptor
+}
+
+impl Acceptor {
+    pub fn new (ssl: OpenSSLConfig) -> Acceptor {
+        Acceptor {
+            inner: OpenSSLAcceptor::new(ssl)
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for Acceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static
+{
+    type Stream = SslStream<I>;
+    type Service = AddExtension<S, Option<Client>>;
+    type Future = Pin<Box<
+        dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send
+    >>;
+
+    fn accept (&self, stream: I, service: S) -> Self::Future {
+        let handshake = self.inner.accept(stream, service);
+        Box::pin(async move {
+            let (stream, service) = handshake.await?;
+            let client = Client::new(stream.ssl());
+            Ok((stream, Extension(client).layer(service)))
+        })
+    }
+}
@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+use reqwest::{Request, Response};
+use crate::client::Timeouts;
+
+type SendError = Box<dyn Error + Send + Sync>;
+
+// the same error of a std socket that timed out
+#[cfg(unix)]
+async fn limit<T, F> (
+    timeout: Option<Duration>,
+    future: F,
+    message: &str
+) -> Result<T, SendError>
+where
+    F: std::future::Future<Output = Result<T, SendError>>
+{
+    use std::io::{Error, ErrorKind};
+
+    match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, future).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, message).into())
+        },
+        None => future.await
+    }
+}
+
+#[cfg(unix)]
+pub async fn send (
+    socket: &Path,
+    request: Request,
+    timeouts: Timeouts
+) -> Result<Response, SendError> {
+    use axum::http::{header, HeaderValue};
+    use futures_util::StreamExt;
+    use futures_util::stream::unfold;
+    use hyper::client::conn::http1::handshake;
+    use hyper_util::rt::TokioIo;
+    use tokio::net::UnixStream;
+
+    let timeout = request.timeout().cloned();
+    let host = request.url().host_str().unwrap_or("localhost").to_string();
+    let mut request: axum::http::Request<reqwest::Body> = request.try_into()?;
+    // over a socket the request target is just the path
+    let path = match request.uri().path_and_query() {
+        Some(path) => path.as_str().to_string(),
+        None => "/".to_string()
+    };
+    *request.uri_mut() = path.parse()?;
+    request.headers_mut().insert(header::HOST, HeaderValue::from_str(&host)?);
+
+    let send = async move {
+        let (mut sender, conn) = limit(Some(timeouts.connect), async {
+            let stream = UnixStream::connect(socket).await?;
+            Ok(handshake(TokioIo::new(stream)).await?)
+        }, "Connection to the socket timed out").await?;
+        tokio::spawn(async move {
+            let _ = conn.with_upgrades().await;
+        });
+        let response = limit(Some(timeouts.response()), async {
+            Ok(sender.send_request(request).await?)
+        }, "Read from the socket timed out").await?;
+
+        // the read timeout also applies between chunks of the body
+        let (parts, body) = response.into_parts();
+        let body = unfold(
+            Some(axum::body::Body::new(body).into_data_stream()),
+            move |body| async move {
+                let mut body = body?;
+                let chunk = limit(timeouts.read, async {
+                    Ok(body.next().await.transpose()?)
+                }, "Read from the socket timed out").await;
+                match chunk {
+                    Ok(Some(chunk)) => Some((Ok(chunk), Some(body))),
+                    Ok(None) => None,
+                    Err(err) => Some((Err(err), None))
+                }
+            }
+        );
+        Ok(Response::from(axum::http::Response::from_parts(
+            parts,
+            reqwest::Body::wrap_stream(body)
+        )))
+    };
+    limit(timeout, send, "Request to the socket timed out").await
+}
+
+#[cfg(not(unix))]
+pub async fn send (
+    _socket: &Path,
+    _request: Request,
+    _timeouts: Timeouts
+) -> Result<Response, SendError> {
+    Err("Unix sockets are not supported on this platform".into())
+}
+
+// the status of a GET request, used by the health checks
+#[cfg(unix)]
+pub fn status (socket: &Path, path: &str, timeout: Duration) -> Option<u16> {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(socket).ok()?;
+    stream.set_read_timeout(Some(timeout)).ok()?;
+    stream.set_write_timeout(Some(timeout)).ok()?;
+    write!(stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    ).ok()?;
+
+    let mut data: Vec<u8> = Vec::new();
+    let _ = stream.take(65536).read_to_end(&mut data);
+    let line = data.split(|byte| *byte == b'\n').next()?;
+    String::from_utf8_lossy(line).split(' ').nth(1)?.parse().ok()
+}
+
+#[cfg(not(unix))]
+pub fn status (_socket: &Path, _path: &str, _timeout: Duration) -> Option<u16> {
+    None
+}
@@ -0,0 +1,446 @@
+use std::error::Error;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_derive::Serialize;
+use reqwest::blocking::Client;
+use crate::config;
+use crate::debug::log;
+use crate::unix;
+
+const MAX_FAILS: u32 = 1;
+const FAIL_TIMEOUT: u64 = 10;
+const REPLICAS: u32 = 160;
+const MAX_WEIGHT: u32 = 100;
+const INTERVAL: u64 = 10;
+const TIMEOUT: u64 = 2;
+const RISE: u32 = 2;
+const FALL: u32 = 3;
+const COOLDOWN: u64 = 30;
+
+enum Strategy {
+    RoundRobin,
+    LeastConnections,
+    Hash(Option<String>)
+}
+
+struct Backend {
+    url: String,
+    base: String,
+    socket: Option<PathBuf>,
+    weight: u32,
+    active: AtomicUsize,
+    fails: AtomicU32,
+    failed: Mutex<Option<Instant>>,
+    healthy: AtomicBool,
+    checks: AtomicU32
+}
+
+impl Backend {
+    // a max_fails of 0 disables the exclusion of failed backends
+    fn available (&self, max_fails: u32, fail_timeout: Duration) -> bool {
+        if !self.healthy.load(Ordering::Relaxed) {
+            return false;
+        }
+        if max_fails == 0 || self.fails.load(Ordering::Relaxed) < max_fails {
+            return true;
+        }
+        let failed = match self.failed.lock() {
+            Ok(failed) => *failed,
+            Err(err) => *err.into_inner()
+        };
+        match failed {
+            Some(failed) => failed.elapsed() >= fail_timeout,
+            None => true
+        }
+    }
+}
+
+struct Breaker {
+    name: String,
+    failures: u32,
+    cooldown: Duration,
+    count: AtomicU32,
+    opened: Mutex<Option<Instant>>
+}
+
+impl Breaker {
+    // after the cooldown a single request is let through to probe the
+    // upstream, restarting the cooldown until it reports back
+    fn allow (&self) -> bool {
+        let mut opened = match self.opened.lock() {
+            Ok(opened) => opened,
+            Err(err) => err.into_inner()
+        };
+        match *opened {
+            None => true,
+            Some(time) if time.elapsed() >= self.cooldown => {
+                *opened = Some(Instant::now());
+                true
+            },
+            Some(_) => false
+        }
+    }
+
+    fn success (&self) {
+        self.count.store(0, Ordering::Relaxed);
+        let mut opened = match self.opened.lock() {
+            Ok(opened) => opened,
+            Err(err) => err.into_inner()
+        };
+        if opened.take().is_some() {
+            log(&format!("Circuit of upstream <{}> closed", self.name));
+        }
+    }
+
+    fn failure (&self) {
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        if count < self.failures {
+            return;
+        }
+        let mut opened = match self.opened.lock() {
+            Ok(opened) => opened,
+            Err(err) => err.into_inner()
+        };
+        if opened.replace(Instant::now()).is_none() {
+            log(&format!("Circuit of upstream <{}> opened", self.name));
+        }
+    }
+}
+
+pub struct Peer {
+    backend: Arc<Backend>,
+    breaker: Option<Arc<Breaker>>
+}
+
+impl Peer {
+    pub fn url (&self, path: &str) -> String {
+        format!("{}{}", self.backend.base.trim_end_matches('/'), path)
+    }
+
+    pub fn socket (&self) -> Option<&Path> {
+        self.backend.socket.as_deref()
+    }
+
+    pub fn success (&self) {
+        self.backend.fails.store(0, Ordering::Relaxed);
+        if let Some(breaker) = &self.breaker {
+            breaker.success();
+        }
+    }
+
+    pub fn failure (&self) {
+        self.backend.fails.fetch_add(1, Ordering::Relaxed);
+        match self.backend.failed.lock() {
+            Ok(mut failed) => *failed = Some(Instant::now()),
+            Err(err) => *err.into_inner() = Some(Instant::now())
+        }
+        if let Some(breaker) = &self.breaker {
+            breaker.failure();
+        }
+    }
+}
+
+impl Drop for Peer {
+    fn drop (&mut self) {
+        self.backend.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn hash (key: &str) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in key.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+pub struct Pool {
+    name: String,
+    strategy: Strategy,
+    backends: Vec<Arc<Backend>>,
+    ring: Vec<(u32, usize)>,
+    next: AtomicUsize,
+    max_fails: u32,
+    fail_timeout: Duration,
+    health: Option<config::Health>,
+    breaker: Option<Arc<Breaker>>,
+    pub timeout: Option<Duration>,
+    pub retries: u32,
+    pub http2: bool
+}
+
+#[derive(Serialize)]
+pub struct Status {
+    url: String,
+    weight: u32,
+    healthy: bool,
+    available: bool,
+    active: usize,
+    fails: u32
+}
+
+impl Pool {
+    fn new (name: &str, upstream: &config::Upstream) -> Result<Pool, String> {
+        let strategy = match upstream.strategy.as_deref() {
+            None | Some("round_robin") => Strategy::RoundRobin,
+            Some("least_connections") => Strategy::LeastConnections,
+            Some("hash") => Strategy::Hash(
+                upstream.hash.as_ref().map(|header| header.to_lowercase())
+            ),
+            Some(strategy) => {
+                return Err(format!(
+                    "Unknown strategy <{}> in upstream <{}>", strategy, name
+                ));
+            }
+        };
+        if upstream.backends.is_empty() {
+            return Err(format!("Upstream <{}> has no backends", name));
+        }
+
+        let mut backends: Vec<Arc<Backend>> = Vec::new();
+        let mut ring: Vec<(u32, usize)> = Vec::new();
+        for (index, backend) in upstream.backends.iter().enumerate() {
+            let weight = backend.weight.unwrap_or(1).max(1);
+            if weight > MAX_WEIGHT {
+                return Err(format!(
+                    "Weight of <{}> in upstream <{}> is above {}",
+                    backend.url, name, MAX_WEIGHT
+                ));
+            }
+            for replica in 0..REPLICAS * weight {
+                let point = hash(&format!("{}-{}", backend.url, replica));
+                ring.push((point, index));
+            }
+            // unix:/path/to.sock
+            let socket = backend.url.strip_prefix("unix:").map(PathBuf::from);
+            backends.push(Arc::new(Backend {
+                url: backend.url.clone(),
+                base: match socket {
+                    Some(_) => "http://localhost".to_string(),
+                    None => backend.url.clone()
+                },
+                socket,
+                weight,
+                active: AtomicUsize::new(0),
+                fails: AtomicU32::new(0),
+                failed: Mutex::new(None),
+                healthy: AtomicBool::new(true),
+                checks: AtomicU32::new(0)
+            }));
+        }
+        ring.sort();
+
+        Ok(Pool {
+            name: name.to_string(),
+            strategy,
+            backends,
+            ring,
+            next: AtomicUsize::new(0),
+            max_fails: upstream.max_fails.unwrap_or(MAX_FAILS),
+            fail_timeout: Duration::from_secs(
+                upstream.fail_timeout.unwrap_or(FAIL_TIMEOUT)
+            ),
+            health: upstream.health.clone(),
+            breaker: upstream.breaker.as_ref().map(|breaker| {
+                Arc::new(Breaker {
+                    name: name.to_string(),
+                    failures: breaker.failures.max(1),
+                    cooldown: Duration::from_secs(
+                        breaker.cooldown.unwrap_or(COOLDOWN)
+                    ),
+                    count: AtomicU32::new(0),
+                    opened: Mutex::new(None)
+                })
+            }),
+            timeout: upstream.timeout.map(Duration::from_secs),
+            retries: upstream.retries.unwrap_or(0),
+            http2: upstream.http2.unwrap_or(false)
+        })
+    }
+
+    fn status (&self) -> Vec<Status> {
+        self.backends.iter().enumerate().map(|(index, backend)| Status {
+            url: backend.url.clone(),
+            weight: backend.weight,
+            healthy: backend.healthy.load(Ordering::Relaxed),
+            available: self.available(index),
+            active: backend.active.load(Ordering::Relaxed),
+            fails: backend.fails.load(Ordering::Relaxed)
+        }).collect()
+    }
+
+    pub fn urls (&self) -> Vec<String> {
+        self.backends.iter().map(|backend| backend.base.clone()).collect()
+    }
+
+    fn available (&self, index: usize) -> bool {
+        self.backends[index].available(self.max_fails, self.fail_timeout)
+    }
+
+    pub fn select (
+        &self,
+        headers: &HashMap<String, String>,
+        addr: Option<SocketAddr>
+    ) -> Result<Peer, String> {
+        if let Some(breaker) = &self.breaker {
+            if !breaker.allow() {
+                return Err(format!(
+                    "Circuit of upstream <{}> is open", self.name
+                ));
+            }
+        }
+        let available: Vec<usize> = (0..self.backends.len())
+            .filter(|index| self.available(*index)).collect();
+        if available.is_empty() {
+            return Err(format!(
+                "No backend available in upstream <{}>", self.name
+            ));
+        }
+
+        let index = match &self.strategy {
+            Strategy::RoundRobin => {
+                let total: u32 = available.iter()
+                    .map(|index| self.backends[*index].weight).sum();
+                let mut n = (self.next.fetch_add(1, Ordering::Relaxed) %
+                    total as usize) as u32;
+                let mut selected = available[0];
+                for index in &available {
+                    let weight = self.backends[*index].weight;
+                    if n < weight {
+                        selected = *index;
+                        break;
+                    }
+                    n -= weight;
+                }
+                selected
+            },
+            Strategy::LeastConnections => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed) %
+                    available.len();
+                let mut selected = available[start];
+                for index in available.iter().cycle().skip(start)
+                    .take(available.len())
+                {
+                    let a = &self.backends[*index];
+                    let b = &self.backends[selected];
+                    let load_a = a.active.load(Ordering::Relaxed) as u64 *
+                        b.weight as u64;
+                    let load_b = b.active.load(Ordering::Relaxed) as u64 *
+                        a.weight as u64;
+                    if load_a < load_b {
+                        selected = *index;
+                    }
+                }
+                selected
+            },
+            Strategy::Hash(header) => {
+                let key = header.as_ref()
+                    .and_then(|header| headers.get(header).cloned())
+                    .or(addr.map(|addr| addr.ip().to_string()))
+                    .unwrap_or_default();
+                let key = hash(&key);
+                let start = self.ring.partition_point(|(point, _)| {
+                    *point < key
+                });
+                self.ring.iter().cycle().skip(start).take(self.ring.len())
+                    .map(|(_, index)| *index)
+                    .find(|index| self.available(*index))
+                    .unwrap_or(available[0])
+            }
+        };
+
+        let backend = self.backends[index].clone();
+        backend.active.fetch_add(1, Ordering::Relaxed);
+        Ok(Peer {
+            backend,
+            breaker: self.breaker.clone()
+        })
+    }
+}
+
+fn check (pool: Weak<Pool>, health: config::Health) {
+    let interval = Duration::from_secs(health.interval.unwrap_or(INTERVAL));
+    let rise = health.rise.unwrap_or(RISE);
+    let fall = health.fall.unwrap_or(FALL);
+    let timeout = Duration::from_secs(health.timeout.unwrap_or(TIMEOUT));
+    let client = match Client::builder().timeout(timeout).build() {
+        Ok(client) => client,
+        Err(err) => {
+            log(&format!("Unable to start health checks!\n{:#}", err));
+            return;
+        }
+    };
+
+    while let Some(pool) = pool.upgrade() {
+        for backend in &pool.backends {
+            let status = match &backend.socket {
+                Some(socket) => unix::status(socket, &health.path, timeout),
+                None => client.get(format!(
+                    "{}{}", backend.base.trim_end_matches('/'), health.path
+                )).send().ok().map(|response| response.status().as_u16())
+            };
+            let passed = status.is_some_and(|status| match health.status {
+                Some(expected) => status == expected,
+                None => (200..300).contains(&status)
+            });
+
+            let healthy = backend.healthy.load(Ordering::Relaxed);
+            if passed == healthy {
+                backend.checks.store(0, Ordering::Relaxed);
+                continue;
+            }
+            let checks = backend.checks.fetch_add(1, Ordering::Relaxed) + 1;
+            if checks >= if healthy {fall} else {rise} {
+                backend.healthy.store(passed, Ordering::Relaxed);
+                backend.checks.store(0, Ordering::Relaxed);
+                log(&format!("Backend <{}> of upstream <{}> is {}",
+                    backend.url, pool.name, if passed {"up"} else {"down"}
+                ));
+            }
+        }
+        drop(pool);
+        thread::sleep(interval);
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Upstreams {
+    pools: Arc<HashMap<String, Arc<Pool>>>
+}
+
+impl Upstreams {
+    pub fn new (
+        upstreams: &HashMap<String, config::Upstream>
+    ) -> Result<Upstreams, Box<dyn Error>> {
+        let mut pools: HashMap<String, Arc<Pool>> = HashMap::new();
+        for (name, upstream) in upstreams {
+            pools.insert(name.clone(), Arc::new(Pool::new(name, upstream)?));
+        }
+        for pool in pools.values() {
+            if let Some(health) = pool.health.clone() {
+                let pool = Arc::downgrade(pool);
+                thread::spawn(move || check(pool, health));
+            }
+        }
+
+        Ok(Upstreams {
+            pools: Arc::new(pools)
+        })
+    }
+
+    pub fn get (&self, name: &str) -> Option<Arc<Pool>> {
+        self.pools.get(name).cloned()
+    }
+
+    pub fn status (&self) -> HashMap<String, Vec<Status>> {
+        self.pools.iter()
+            .map(|(name, pool)| (name.clone(), pool.status()))
+            .collect()
+    }
+}